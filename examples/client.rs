@@ -1,15 +1,10 @@
 #![warn(rust_2018_idioms)]
 
-use futures::stream::StreamExt;
-use std::{env, io, str};
+use std::env;
 use std::time::Duration;
-use fx_communication::{FxCodec, Message, Address, NakWithError, Request, Response, Command, WriteWordsCommand, ReadWordsCommand, Client};
-use tokio_util::codec::{Decoder, Encoder};
-use tokio::io::{AsyncReadExt, AsyncWriteExt, AsyncWrite};
-use bytes::BytesMut;
-use futures::SinkExt;
+use fx_communication::{Client, Device};
 
-use tokio_serial::{FlowControl, SerialPort, SerialPortBuilder, SerialPortBuilderExt};
+use tokio_serial::SerialPortBuilderExt;
 
 #[cfg(unix)]
 const DEFAULT_TTY: &str = "/dev/tty.usbserial-AWCUb116L16";
@@ -17,6 +12,7 @@ const DEFAULT_TTY: &str = "/dev/tty.usbserial-AWCUb116L16";
 const DEFAULT_TTY: &str = "COM1";
 
 #[tokio::main]
+#[allow(unreachable_code)]
 async fn main() -> tokio_serial::Result<()> {
     let mut args = env::args();
     let tty_path = args.nth(1).unwrap_or_else(|| DEFAULT_TTY.into());
@@ -30,15 +26,18 @@ async fn main() -> tokio_serial::Result<()> {
     //     .expect("Unable to set serial port exclusive to false");
 
     let mut client = Client::new(5,255, port);
+    let device: Device = "D106".parse().expect("valid device");
     let mut v1= 0i16;
     let mut v2= 10i32;
 
     loop {
-        client.write_i16("D0106".to_string(), v1).await;
-        //client.write_i32("D0105".to_string(), v2).await;
+        if let Err(error) = client.write_i16(device, v1).await {
+            println!("Write error: {}", error);
+        }
+        //client.write_i32("D105".parse().unwrap(), v2).await;
         tokio::time::sleep(Duration::from_millis(1)).await;
         print!(">");
-        match client.read_i16("D0106".to_string()).await {
+        match client.read_i16(device).await {
             Ok(result) => {
                 println!("Read: {}", result);
             },
@@ -9,7 +9,10 @@ use std::collections::HashMap;
 use fx_communication::{FxCodec, Message,  Response};
 
 use tokio_serial::{SerialPortBuilderExt};
-use fx_communication::Command::{ReadWords, WriteWords};
+use fx_communication::Command::{
+    ExtendedReadWords, ExtendedTestWords, ExtendedWriteWords, Global, LoopbackTest, Raw, ReadBits, ReadPlcType,
+    ReadWords, RemoteRun, RemoteStop, TestBits, TestWords, WriteBits, WriteWords,
+};
 use futures::SinkExt;
 
 #[cfg(unix)]
@@ -39,35 +42,82 @@ async fn main() -> tokio_serial::Result<()> {
 
 
     let mut register = HashMap::new();
+    let mut bits = HashMap::new();
     while let Some(message_result) = reader.next().await {
         match message_result {
             Ok(message) => {
                 match message {
                     Message::Request(p) => {
                         //println!("Received Request: {:?}", &p);
-                        match &p.command {
-                            WriteWords(c) => {
-                                let v = register.entry(c.head_device.clone()).or_insert(c.data.clone());
-                                println!("replaced in register: {} old: {} new: {}", c.head_device,  v, &c.data);
+                        let response = match &p.command {
+                            WriteWords(c) | ExtendedWriteWords(c) => {
+                                let v = register.entry(c.head_device).or_insert(c.data.clone());
+                                println!("replaced in register: {} old: {:04X?} new: {:04X?}", c.head_device,  v, &c.data);
                                 *v = c.data.clone();
-
-                                writer.send(Message::Ack(p.address)).await?;
-                                writer.flush().await?;
-
+                                None
                             },
-                            ReadWords(c) => {
-                                let v = register.entry(c.head_device.clone());
+                            ReadWords(c) | ExtendedReadWords(c) => {
+                                let v = register.entry(c.head_device);
 
                                 let r = match v {
                                     Occupied(e) => {
                                         e.get().clone()
                                     },
                                     Vacant(_e) => {
-                                        "0000".to_string()
+                                        vec![0]
                                     }
                                 };
                                 //println!("read from register: {:?}", r);
-                                writer.send(Message::Response(Response::new(p.address, r))).await?;
+                                Some(Response::from_words(p.address, &r))
+                            },
+                            WriteBits(c) => {
+                                for (i, b) in c.data.iter().enumerate() {
+                                    if let Ok(device) = c.head_device.offset(i as u32) {
+                                        bits.insert(device, *b);
+                                    }
+                                }
+                                println!("set bits from {}: {:?}", c.head_device, &c.data);
+                                None
+                            },
+                            TestBits(c) => {
+                                for (device, value) in &c.points {
+                                    bits.insert(*device, *value);
+                                }
+                                None
+                            },
+                            TestWords(c) | ExtendedTestWords(c) => {
+                                for (device, value) in &c.points {
+                                    register.insert(*device, vec![*value]);
+                                }
+                                None
+                            },
+                            RemoteRun | RemoteStop => None,
+                            ReadPlcType => Some(Response::new(p.address, "F3")),
+                            LoopbackTest(c) => Some(Response::new(p.address, format!("{:02X}{}", c.data.len(), c.data))),
+                            Global(c) => {
+                                // no reply to global commands
+                                println!("global M8126: {}", c.set);
+                                continue;
+                            },
+                            ReadBits(c) => {
+                                let r = (0..c.number_of_device_points as u32)
+                                    .map(|i| c.head_device.offset(i).ok().and_then(|d| bits.get(&d).copied()).unwrap_or(false))
+                                    .collect::<Vec<_>>();
+                                Some(Response::from_bits(p.address, &r))
+                            },
+                            Raw(c) => {
+                                println!("unknown command {}: {}", c.code, c.body);
+                                continue;
+                            },
+                        };
+
+                        match response {
+                            None => {
+                                writer.send(Message::Ack(p.address)).await?;
+                                writer.flush().await?;
+                            },
+                            Some(r) => {
+                                writer.send(Message::Response(r)).await?;
                                 writer.flush().await?;
                                 if let Some(message_result) = reader.next().await {
                                     match message_result {
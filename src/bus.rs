@@ -0,0 +1,224 @@
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, PoisonError};
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::{Mutex, MutexGuard};
+use tokio_serial::SerialStream;
+
+use crate::{Address, Client, ClientConfig, ClientStats};
+
+/// Configuration and statistics kept per station while the line serves others.
+#[derive(Debug, Clone, Default)]
+struct StationState {
+    config: ClientConfig,
+    stats: ClientStats,
+}
+
+fn lock<T>(mutex: &std::sync::Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Master of a multi-drop RS-485 line with several stations on one port.
+///
+/// Only one transaction is on the line at a time. Stations waiting for the
+/// line get it in the order they asked for it, so a busy polling loop of one
+/// station doesn't starve the others.
+pub struct Bus<T = SerialStream> {
+    client: Arc<Mutex<Client<T>>>,
+    plc: u8,
+    /// Configuration of new stations, the one of the client the bus was built from.
+    default_config: ClientConfig,
+    stations: Arc<std::sync::Mutex<HashMap<u8, Arc<std::sync::Mutex<StationState>>>>>,
+}
+
+impl<T> Clone for Bus<T> {
+    fn clone(&self) -> Self {
+        Bus {
+            client: self.client.clone(),
+            plc: self.plc,
+            default_config: self.default_config.clone(),
+            stations: self.stations.clone(),
+        }
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite> Bus<T> {
+    /// A bus over `transport` addressing the stations with PLC number `plc`,
+    /// usually FF for the CPU the computer link board is attached to.
+    pub fn new(plc: u8, transport: T) -> Self {
+        Bus::from_client(Client::new(0, plc, transport))
+    }
+
+    /// Takes over the line of `client`, its configuration becomes the default
+    /// of new stations.
+    pub fn from_client(client: Client<T>) -> Self {
+        Bus {
+            plc: client.address.plc,
+            default_config: client.config().clone(),
+            client: Arc::new(Mutex::new(client)),
+            stations: Default::default(),
+        }
+    }
+
+    /// Handle of `station`. Handles of the same station share configuration
+    /// and statistics.
+    pub fn station(&self, station: u8) -> Station<T> {
+        let state = lock(&self.stations)
+            .entry(station)
+            .or_insert_with(|| {
+                let config = self.default_config.clone();
+                Arc::new(std::sync::Mutex::new(StationState { config, stats: ClientStats::default() }))
+            })
+            .clone();
+        Station {
+            client: self.client.clone(),
+            address: Address::new(station, self.plc),
+            state,
+        }
+    }
+}
+
+/// Cheap handle to one station of a [`Bus`].
+pub struct Station<T = SerialStream> {
+    client: Arc<Mutex<Client<T>>>,
+    address: Address,
+    state: Arc<std::sync::Mutex<StationState>>,
+}
+
+impl<T> Clone for Station<T> {
+    fn clone(&self) -> Self {
+        Station {
+            client: self.client.clone(),
+            address: self.address,
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl<T> Station<T> {
+    pub fn address(&self) -> Address {
+        self.address
+    }
+
+    pub fn config(&self) -> ClientConfig {
+        lock(&self.state).config.clone()
+    }
+
+    /// Timeouts and retries used for this station only.
+    pub fn set_config(&self, config: ClientConfig) {
+        lock(&self.state).config = config;
+    }
+
+    pub fn stats(&self) -> ClientStats {
+        lock(&self.state).stats.clone()
+    }
+
+    pub fn reset_stats(&self) {
+        lock(&self.state).stats = ClientStats::default();
+    }
+
+    /// Waits for the line and returns the client addressing this station.
+    /// Other stations wait until the guard is dropped, so keep it for the
+    /// requests that belong together only.
+    pub async fn lock(&self) -> StationGuard<'_, T> {
+        let mut client = self.client.lock().await;
+        let state = lock(&self.state).clone();
+        client.address = self.address;
+        client.set_config(state.config);
+        client.replace_stats(state.stats);
+        StationGuard { client, state: &self.state }
+    }
+}
+
+/// The line, addressed to one station. Dereferences to [`Client`].
+pub struct StationGuard<'a, T> {
+    client: MutexGuard<'a, Client<T>>,
+    state: &'a std::sync::Mutex<StationState>,
+}
+
+impl<T> Deref for StationGuard<'_, T> {
+    type Target = Client<T>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.client
+    }
+}
+
+impl<T> DerefMut for StationGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.client
+    }
+}
+
+impl<T> Drop for StationGuard<'_, T> {
+    fn drop(&mut self) {
+        let mut state = lock(self.state);
+        state.config = self.client.config().clone();
+        state.stats = self.client.replace_stats(ClientStats::default());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Device, FxCodec, Message, Response};
+    use futures::{SinkExt, StreamExt};
+    use std::time::Duration;
+    use tokio::io::duplex;
+    use tokio_util::codec::Framed;
+
+    #[tokio::test]
+    async fn stations_share_the_line() {
+        let (line, plc) = duplex(1024);
+        let bus = Bus::new(0xFF, line);
+        let mut plc = Framed::new(plc, FxCodec::new());
+        tokio::spawn(async move {
+            while let Some(Ok(message)) = plc.next().await {
+                if let Message::Request(request) = message {
+                    let station = request.address.station;
+                    // a stale answer of station 1 on the line while station 2 is asked
+                    if station == 2 {
+                        plc.send(Message::Response(Response::new(Address::new(1, 0xFF), "0001".to_string()))).await.unwrap();
+                    }
+                    plc.send(Message::Response(Response::new(request.address, format!("{:04X}", station)))).await.unwrap();
+                }
+            }
+        });
+
+        let device: Device = "D0".parse().unwrap();
+        let one = bus.station(1);
+        let two = bus.station(2);
+        two.set_config(ClientConfig { response_timeout: Duration::from_millis(300), ..ClientConfig::default() });
+        let tasks: Vec<_> = (0..4)
+            .map(|i| {
+                let station = if i % 2 == 0 { one.clone() } else { two.clone() };
+                tokio::spawn(async move { station.lock().await.read_i16(device).await.unwrap() })
+            })
+            .collect();
+        let mut values = Vec::new();
+        for task in tasks {
+            values.push(task.await.unwrap());
+        }
+        assert_eq!(values, vec![1, 2, 1, 2]);
+
+        assert_eq!(one.stats().requests, 2);
+        assert_eq!(one.stats().mismatched_frames(), 0);
+        assert_eq!(two.stats().mismatched_responses, 2);
+        assert_eq!(bus.station(2).config().response_timeout, Duration::from_millis(300));
+        assert_eq!(one.config().response_timeout, ClientConfig::default().response_timeout);
+    }
+
+    #[tokio::test]
+    async fn new_stations_get_the_config_of_the_client() {
+        let (line, _plc) = duplex(1024);
+        let mut client = Client::new(0, 0xFF, line);
+        let config = ClientConfig { retries: 5, ..ClientConfig::default() };
+        client.set_config(config.clone());
+        let bus = Bus::from_client(client);
+        let one = bus.station(1);
+        let _line = one.lock().await;
+        // another station holds the line meanwhile
+        assert_eq!(bus.station(2).config(), config);
+    }
+}
@@ -0,0 +1,1059 @@
+use std::io;
+use std::time::{Duration, Instant};
+
+use futures::{SinkExt, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite, ReadHalf, WriteHalf};
+use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio::sync::mpsc;
+use tokio::time::{timeout, timeout_at};
+use tokio_serial::{DataBits, Parity, SerialPortBuilderExt, SerialStream, StopBits};
+use tokio_util::codec::{Decoder, FramedRead, FramedWrite};
+
+use crate::value::{pack_string, unpack_string};
+use crate::{
+    invalid_input, DecodeError, Address, Command, Device, Error, FxCodec, GlobalCommand, LoopbackTestCommand, Message, NakErrorCode, NakWithError, PlcModel,
+    ProtocolFormat, ReadBitsCommand, Request, Response, ResponseData, SumCheck, TestBitsCommand, TestWordsCommand, PlcValue, WordOrder, WriteBitsCommand,
+    GLOBAL_STATION, MAX_BIT_POINTS, MAX_READ_BIT_WORDS, MAX_TEST_BITS, MAX_TEST_WORDS, MAX_WORD_POINTS,
+    MAX_WRITE_BIT_WORDS,
+};
+
+/// Value for [`Client::write_scattered`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ScatteredValue {
+    Bit(bool),
+    Word(u16),
+}
+
+/// Timeouts and retries of [`Client`] requests.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientConfig {
+    /// Time from sending a request until the station's ACK, NAK or STX frame is complete.
+    pub response_timeout: Duration,
+    /// Time allowed to send the ACK for an STX response.
+    pub ack_timeout: Duration,
+    /// Number of times a failed request is sent again.
+    pub retries: u32,
+    /// Wait before a retry, bytes arriving meanwhile are discarded.
+    pub retry_backoff: Duration,
+    pub retry_on_timeout: bool,
+    /// Retry when the station answers NAK with a sum check error.
+    pub retry_on_sum_check_nak: bool,
+    /// Retry when the answer can't be decoded.
+    pub retry_on_decode_error: bool,
+    /// Fail with [`Error::AddressMismatch`] on frames from another station or
+    /// PLC number instead of dropping them and waiting on.
+    pub address_mismatch_is_error: bool,
+    /// CPU whose frame size limits are used to split batch reads and writes,
+    /// set by [`Client::plc_model`]. The protocol limits apply while unknown.
+    pub plc_model: Option<PlcModel>,
+    /// Register order of 32 bit values.
+    pub word_order: WordOrder,
+    /// With Format 1 and [`SumCheck::Tolerant`], how long the line must stay
+    /// quiet after an ETX before the frame is taken as one without sum check.
+    pub sum_check_wait: Duration,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        ClientConfig {
+            response_timeout: Duration::from_secs(1),
+            ack_timeout: Duration::from_millis(500),
+            retries: 2,
+            retry_backoff: Duration::from_millis(50),
+            retry_on_timeout: true,
+            retry_on_sum_check_nak: true,
+            retry_on_decode_error: true,
+            address_mismatch_is_error: false,
+            plc_model: None,
+            word_order: WordOrder::default(),
+            sum_check_wait: Duration::from_millis(20),
+        }
+    }
+}
+
+impl ClientConfig {
+    pub fn is_retryable(&self, error: &Error) -> bool {
+        match error {
+            Error::Timeout => self.retry_on_timeout,
+            Error::Nak { error_code: NakErrorCode::SumCheck, .. } => self.retry_on_sum_check_nak,
+            Error::Decode(_) => self.retry_on_decode_error,
+            _ => false,
+        }
+    }
+}
+
+/// Counters of the requests of a [`Client`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientStats {
+    pub requests: u64,
+    /// Requests sent again after a retryable failure.
+    pub retries: u64,
+    /// Attempts without answer in time, including those retried.
+    pub timeouts: u64,
+    /// Frames received with another station or PLC number than the pending request.
+    pub mismatched_acks: u64,
+    pub mismatched_naks: u64,
+    pub mismatched_responses: u64,
+}
+
+impl ClientStats {
+    pub fn mismatched_frames(&self) -> u64 {
+        self.mismatched_acks + self.mismatched_naks + self.mismatched_responses
+    }
+
+    fn count_mismatch(&mut self, message: &Message) {
+        match message {
+            Message::Ack(_) => self.mismatched_acks += 1,
+            Message::NakWithError(_) => self.mismatched_naks += 1,
+            Message::Response(_) => self.mismatched_responses += 1,
+            Message::Request(_) | Message::Nak(_) => {},
+        }
+    }
+}
+
+/// Talks to one station over any byte stream: a serial port, a TCP
+/// connection to a serial device server or an in-memory pipe.
+///
+/// The request methods are cancel safe, they can be used in `tokio::select!`
+/// and `tokio::time::timeout`. When a request is dropped before its answer
+/// was handled, the next call first waits for that answer and acknowledges it.
+pub struct Client<T = SerialStream> {
+    pub address: Address,
+    pub msg_wait_time: u8,
+    reader: FramedRead<ReadHalf<T>, FxCodec>,
+    writer: FramedWrite<WriteHalf<T>, FxCodec>,
+    on_demand: Option<mpsc::UnboundedSender<Response>>,
+    config: ClientConfig,
+    stats: ClientStats,
+    /// The reader returned an error, the `None` that follows doesn't mean the end of the stream.
+    decode_failed: bool,
+    /// Set while a transaction is on the line, left behind if its future is dropped.
+    in_flight: Option<InFlight>,
+    /// Time from sending the last answered request to its answer.
+    round_trip: Duration,
+}
+
+/// A transaction that may still get an answer.
+#[derive(Debug, Clone)]
+struct InFlight {
+    address: Address,
+    expected_length: Option<usize>,
+    /// Set once the request was sent.
+    deadline: Option<Instant>,
+    /// The answer was received, only the ACK to it may still be pending.
+    answered: bool,
+}
+
+impl Client<SerialStream> {
+    /// Opens `path` with the computer link defaults of D8120: 7 data bits,
+    /// even parity and 1 stop bit. Use [`Client::new`] with a port opened by
+    /// hand for other settings.
+    pub fn serial(station: u8, plc: u8, path: &str, baud_rate: u32) -> tokio_serial::Result<Self> {
+        let port = tokio_serial::new(path, baud_rate)
+            .data_bits(DataBits::Seven)
+            .parity(Parity::Even)
+            .stop_bits(StopBits::One)
+            .open_native_async()?;
+        Ok(Client::new(station, plc, port))
+    }
+}
+
+impl Client<TcpStream> {
+    /// Connects to a serial device server that passes the bytes through unchanged.
+    pub async fn tcp(station: u8, plc: u8, addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let stream = TcpStream::connect(addr).await?;
+        // frames are short and each one waits for an answer
+        stream.set_nodelay(true)?;
+        Ok(Client::new(station, plc, stream))
+    }
+}
+
+impl<T> Client<T> {
+    pub fn config(&self) -> &ClientConfig {
+        &self.config
+    }
+
+    pub fn set_config(&mut self, config: ClientConfig) {
+        self.config = config;
+    }
+
+    pub fn stats(&self) -> &ClientStats {
+        &self.stats
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = ClientStats::default();
+    }
+
+    pub(crate) fn replace_stats(&mut self, stats: ClientStats) -> ClientStats {
+        std::mem::replace(&mut self.stats, stats)
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite> Client<T> {
+    pub fn new(station: u8, plc: u8, transport: T) -> Self {
+        let (rx_port, tx_port) = tokio::io::split(transport);
+        let reader = FramedRead::new(rx_port, FxCodec::new());
+        let writer = FramedWrite::new(tx_port, FxCodec::new());
+        Client {
+            address: Address {
+                station,
+                plc,
+            },
+            msg_wait_time: 0,
+            reader,
+            writer,
+            on_demand: None,
+            config: ClientConfig::default(),
+            stats: ClientStats::default(),
+            decode_failed: false,
+            in_flight: None,
+            round_trip: Duration::ZERO,
+        }
+    }
+
+    /// Data the PLC sends on its own with the on-demand function (D8127,
+    /// D8128). Frames are only forwarded while the client reads the line,
+    /// during a request without response data or in
+    /// [`listen_on_demand`](Client::listen_on_demand). While a read is
+    /// pending every STX frame of the station is taken as its answer.
+    /// A new call replaces the previous receiver.
+    pub fn on_demand_events(&mut self) -> mpsc::UnboundedReceiver<Response> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.on_demand = Some(tx);
+        rx
+    }
+
+    /// Reads the line while no request is pending until an on-demand frame
+    /// was forwarded. Combine with `tokio::time::timeout` to bound the wait.
+    pub async fn listen_on_demand(&mut self) -> Result<(), Error> {
+        self.recover().await?;
+        loop {
+            if let Message::Response(r) = self.next_message().await? {
+                self.forward_on_demand(r);
+                return Ok(());
+            }
+        }
+    }
+
+    fn forward_on_demand(&mut self, response: Response) {
+        if let Some(tx) = &self.on_demand {
+            if tx.send(response).is_err() {
+                self.on_demand = None;
+            }
+        }
+    }
+
+    /// Switches framing of both directions, must match the format set in D8120.
+    pub fn set_protocol_format(&mut self, format: ProtocolFormat) {
+        self.reader.decoder_mut().format = format;
+        self.writer.encoder_mut().format = format;
+    }
+
+    /// Must match the sum check setting in D8120 of the station.
+    pub fn set_sum_check(&mut self, sum_check: SumCheck) {
+        self.reader.decoder_mut().sum_check = sum_check;
+        self.writer.encoder_mut().sum_check = sum_check;
+    }
+
+    pub async fn write_i16(&mut self, head_device: Device, value: i16) -> Result<(), Error> {
+        self.write(head_device, value).await
+    }
+
+    pub async fn write_i32(&mut self, head_device: Device, value: i32) -> Result<(), Error> {
+        self.write(head_device, value).await
+    }
+
+    pub async fn read_i32(&mut self, head_device: Device) -> Result<i32, Error> {
+        self.read(head_device).await
+    }
+
+    pub async fn read_i16(&mut self, head_device: Device) -> Result<i16, Error> {
+        self.read(head_device).await
+    }
+
+    /// Reads a value from `head_device` and the registers after it, 32 bit
+    /// values in the configured [`WordOrder`].
+    pub async fn read<V: PlcValue>(&mut self, head_device: Device) -> Result<V, Error> {
+        let words = self.read_words(head_device, V::WORDS).await?;
+        V::from_words(&words, self.config.word_order)
+    }
+
+    pub async fn write<V: PlcValue>(&mut self, head_device: Device, value: V) -> Result<(), Error> {
+        let words = value.to_words(self.config.word_order)?;
+        self.write_words(head_device, &words).await
+    }
+
+    /// Reads text of up to `max_len` ASCII characters packed two per register,
+    /// the first one in the low byte. The text ends at the first NUL.
+    pub async fn read_string(&mut self, head_device: Device, max_len: usize) -> Result<String, Error> {
+        let words = self.read_words(head_device, max_len.div_ceil(2)).await?;
+        unpack_string(&words, max_len)
+    }
+
+    /// Writes ASCII text packed like [`read_string`](Client::read_string) with
+    /// a NUL after it, `text.len() / 2 + 1` registers in all.
+    pub async fn write_string(&mut self, head_device: Device, text: &str) -> Result<(), Error> {
+        let words = pack_string(text)?;
+        self.write_words(head_device, &words).await
+    }
+
+    /// Reads `count` consecutive word devices, or words of 16 bit devices
+    /// each, split into as many requests as the frame size limit of the
+    /// command and the CPU requires.
+    pub async fn read_words(&mut self, head_device: Device, count: usize) -> Result<Vec<u16>, Error> {
+        let limit = if head_device.kind.is_bit() {
+            self.config.plc_model.map_or(MAX_READ_BIT_WORDS, |m| m.max_read_bit_words().min(MAX_READ_BIT_WORDS))
+        } else {
+            self.config.plc_model.map_or(MAX_WORD_POINTS, |m| m.max_read_words().min(MAX_WORD_POINTS))
+        };
+        let mut words = Vec::with_capacity(count);
+        while words.len() < count {
+            let points = (count - words.len()).min(limit as usize) as u8;
+            let device = chunk_head(head_device, words.len())?;
+            match self.read_request(Command::read_words(device, points)).await? {
+                ResponseData::Words(chunk) => words.extend(chunk),
+                other => return Err(unexpected_data(other)),
+            }
+        }
+        Ok(words)
+    }
+
+    /// Writes consecutive word devices starting at `head_device`, split like
+    /// [`read_words`](Client::read_words).
+    pub async fn write_words(&mut self, head_device: Device, values: &[u16]) -> Result<(), Error> {
+        let limit = if head_device.kind.is_bit() {
+            self.config.plc_model.map_or(MAX_WRITE_BIT_WORDS, |m| m.max_write_bit_words().min(MAX_WRITE_BIT_WORDS))
+        } else {
+            self.config.plc_model.map_or(MAX_WORD_POINTS, |m| m.max_write_words().min(MAX_WORD_POINTS))
+        };
+        for (i, chunk) in values.chunks(limit as usize).enumerate() {
+            let device = chunk_head(head_device, i * limit as usize)?;
+            self.write_request(Command::write_words(device, chunk.to_vec())).await?;
+        }
+        Ok(())
+    }
+
+    /// Reads `count` consecutive bit devices, 1 to 256 points.
+    pub async fn read_bits(&mut self, head_device: Device, count: u16) -> Result<Vec<bool>, Error> {
+        match self.read_request(Command::ReadBits(ReadBitsCommand::new(head_device, count))).await? {
+            ResponseData::Bits(bits) => Ok(bits),
+            other => Err(unexpected_data(other)),
+        }
+    }
+
+    /// Writes consecutive bit devices, 1 to 256 points.
+    pub async fn write_bits(&mut self, head_device: Device, values: &[bool]) -> Result<(), Error> {
+        if values.len() > MAX_BIT_POINTS as usize {
+            return Err(invalid_input(format!("{} bit points, 1 to {} allowed", values.len(), MAX_BIT_POINTS)).into());
+        }
+        self.write_request(Command::WriteBits(WriteBitsCommand::new(head_device, values.to_vec()))).await
+    }
+
+    pub async fn set_bit(&mut self, device: Device, value: bool) -> Result<(), Error> {
+        self.write_bits(device, &[value]).await
+    }
+
+    /// Writes bits and words at arbitrary devices, using as few BT and WT
+    /// commands as the point limits allow. Words go out with QT when one of
+    /// the devices in the chunk needs the extended device field.
+    pub async fn write_scattered(&mut self, values: &[(Device, ScatteredValue)]) -> Result<(), Error> {
+        let mut bits = Vec::new();
+        let mut words = Vec::new();
+        for (device, value) in values {
+            match value {
+                ScatteredValue::Bit(b) => bits.push((*device, *b)),
+                ScatteredValue::Word(w) => words.push((*device, *w)),
+            }
+        }
+        for chunk in bits.chunks(MAX_TEST_BITS) {
+            self.write_request(Command::TestBits(TestBitsCommand::new(chunk.to_vec()))).await?;
+        }
+        for chunk in words.chunks(MAX_TEST_WORDS) {
+            let command = TestWordsCommand::new(chunk.to_vec());
+            if chunk.iter().all(|(device, _)| device.code().is_ok()) {
+                self.write_request(Command::TestWords(command)).await?;
+            } else {
+                self.write_request(Command::ExtendedTestWords(command)).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Switches the PLC to RUN, it must be in STOP by the RUN/STOP switch or a previous remote STOP.
+    pub async fn remote_run(&mut self) -> Result<(), Error> {
+        self.write_request(Command::RemoteRun).await
+    }
+
+    pub async fn remote_stop(&mut self) -> Result<(), Error> {
+        self.write_request(Command::RemoteStop).await
+    }
+
+    /// Reads the PC type code of the connected CPU and keeps it in the
+    /// configuration for the frame size limits.
+    pub async fn plc_model(&mut self) -> Result<PlcModel, Error> {
+        let model = match self.read_request(Command::ReadPlcType).await? {
+            ResponseData::PlcType(model) => model,
+            other => return Err(unexpected_data(other)),
+        };
+        self.config.plc_model = Some(model);
+        Ok(model)
+    }
+
+    /// Sends `payload` with the loopback test command and checks that the
+    /// station echoes it unchanged. Returns the round trip time of the attempt
+    /// that was answered. Touches no PLC memory, so it is safe to use to
+    /// check a link.
+    pub async fn loopback(&mut self, payload: &str) -> Result<Duration, Error> {
+        let echo = match self.read_request(Command::LoopbackTest(LoopbackTestCommand::new(payload.to_string()))).await? {
+            ResponseData::Loopback(echo) => echo,
+            other => return Err(unexpected_data(other)),
+        };
+        if echo != payload {
+            return Err(Error::Protocol(format!("loopback sent {:?} but received {:?}", payload, echo)));
+        }
+        Ok(self.round_trip)
+    }
+
+    /// Sets (`true`) or resets M8126 in all stations with the same PLC number.
+    /// Stations don't reply to a global command, so there is nothing to wait for.
+    pub async fn global(&mut self, set: bool) -> Result<(), Error> {
+        let address = Address::new(GLOBAL_STATION, self.address.plc);
+        let command = Command::Global(GlobalCommand::new(set));
+        self.recover().await?;
+        self.in_flight = Some(InFlight { address, expected_length: None, deadline: None, answered: true });
+        self.writer.send(Message::Request(Request::new(address, self.msg_wait_time, command))).await?;
+        self.writer.flush().await?;
+        self.in_flight = None;
+        Ok(())
+    }
+
+    /// Sends a command answered with an STX response and acknowledges the
+    /// response once its data was decoded for the command.
+    async fn read_request(&mut self, command: Command) -> Result<ResponseData, Error> {
+        match self.request(command).await? {
+            Some(response) => Ok(response),
+            None => Err(Error::Protocol("no data in answer to a read command".to_string())),
+        }
+    }
+
+    /// Sends a command answered with ACK.
+    async fn write_request(&mut self, command: Command) -> Result<(), Error> {
+        self.request(command).await.map(|_| ())
+    }
+
+    /// Sends `command` until it succeeds, fails with an error that is not
+    /// retryable or the retries are used up. The line is resynchronized after
+    /// every failed attempt, also the last one.
+    async fn request(&mut self, command: Command) -> Result<Option<ResponseData>, Error> {
+        self.stats.requests += 1;
+        let mut attempt = 0;
+        loop {
+            let result = self.transaction(&command).await;
+            if let Err(Error::Timeout) = result {
+                self.stats.timeouts += 1;
+            }
+            match result {
+                Err(error) if attempt < self.config.retries && self.config.is_retryable(&error) => {
+                    attempt += 1;
+                    self.stats.retries += 1;
+                    self.resync(command.response_length()).await;
+                },
+                Err(error) => {
+                    // a late answer must not be taken for the answer to the next request
+                    if self.in_flight.is_none() {
+                        self.resync(command.response_length()).await;
+                    }
+                    return Err(error);
+                },
+                result => return result,
+            }
+        }
+    }
+
+    /// One request and its answer. Commands with response data wait for STX
+    /// and acknowledge it, data that doesn't decode for the command, like a
+    /// wrong number of points, is answered with NAK. The others wait for ACK
+    /// and take STX frames as on-demand data. Frames from other addresses are
+    /// counted in [`ClientStats`] and dropped.
+    async fn transaction(&mut self, command: &Command) -> Result<Option<ResponseData>, Error> {
+        self.recover().await?;
+        let expected_length = command.response_length();
+        self.in_flight = Some(InFlight { address: self.address, expected_length, deadline: None, answered: false });
+        let result = self.exchange(command, expected_length).await;
+        // the answer of our station may still come after a frame of another one, recover() takes it
+        if !matches!(result, Err(Error::AddressMismatch { .. })) {
+            self.in_flight = None;
+        }
+        result
+    }
+
+    async fn exchange(&mut self, command: &Command, expected_length: Option<usize>) -> Result<Option<ResponseData>, Error> {
+        self.writer.send(Message::Request(Request::new(self.address, self.msg_wait_time, command.clone()))).await?;
+        self.writer.flush().await?;
+        let sent = Instant::now();
+        let deadline = sent + self.config.response_timeout;
+        if let Some(in_flight) = &mut self.in_flight {
+            in_flight.deadline = Some(deadline);
+        }
+        loop {
+            let message = timeout_at(deadline.into(), self.next_message()).await.map_err(|_| Error::Timeout)??;
+            if matches!(message, Message::Ack(_) | Message::NakWithError(_) | Message::Response(_)) && message.address() != self.address {
+                // cross-talk or a stale answer of another station on a multi-drop line
+                self.stats.count_mismatch(&message);
+                if self.config.address_mismatch_is_error {
+                    return Err(Error::AddressMismatch { expected: self.address, actual: message.address() });
+                }
+                continue;
+            }
+            match (message, expected_length) {
+                (Message::Response(r), Some(_)) => {
+                    self.round_trip = sent.elapsed();
+                    if let Some(in_flight) = &mut self.in_flight {
+                        in_flight.answered = true;
+                    }
+                    let data = r.decode(command);
+                    let reply = if data.is_ok() { Message::Ack(self.address) } else { Message::Nak(self.address) };
+                    let reply = async {
+                        self.writer.send(reply).await?;
+                        self.writer.flush().await
+                    };
+                    timeout(self.config.ack_timeout, reply).await.map_err(|_| Error::Timeout)??;
+                    return Ok(Some(data?));
+                },
+                (Message::Ack(_), None) => {
+                    self.round_trip = sent.elapsed();
+                    return Ok(None);
+                },
+                (Message::Response(r), None) => self.forward_on_demand(r),
+                (Message::NakWithError(n), _) => return Err(nak(n)),
+                (message, Some(_)) => {
+                    self.writer.send(Message::Nak(self.address)).await?;
+                    self.writer.flush().await?;
+                    return Err(Error::UnexpectedMessage(message));
+                },
+                (message, None) => return Err(Error::UnexpectedMessage(message)),
+            }
+        }
+    }
+
+    /// Drops whatever arrives during the retry backoff and the rest of the
+    /// read buffer, so a late answer isn't taken for the answer to the retry.
+    /// `expected_length` is the one of the failed request, if the caller
+    /// gives up meanwhile its late answer is still acknowledged.
+    async fn resync(&mut self, expected_length: Option<usize>) {
+        let deadline = Instant::now() + self.config.retry_backoff;
+        self.in_flight = Some(InFlight { address: self.address, expected_length, deadline: Some(deadline), answered: false });
+        loop {
+            match timeout_at(deadline.into(), self.reader.next()).await {
+                Ok(Some(Ok(_))) => {},
+                Ok(Some(Err(_))) => self.decode_failed = true,
+                Ok(None) if self.decode_failed => self.decode_failed = false,
+                Ok(None) | Err(_) => break,
+            }
+        }
+        self.reader.read_buffer_mut().clear();
+        self.reader.decoder_mut().reset();
+        self.in_flight = None;
+    }
+
+    /// Finishes a transaction whose future was dropped: sends what is left in
+    /// the write buffer, waits for the answer until the deadline of the old
+    /// request and acknowledges an STX answer, so the station isn't left
+    /// waiting and the answer isn't taken for the one to the next request.
+    async fn recover(&mut self) -> Result<(), Error> {
+        let Some(in_flight) = self.in_flight.clone() else {
+            return Ok(());
+        };
+        if let Err(error) = self.writer.flush().await {
+            self.in_flight = None;
+            return Err(error.into());
+        }
+        if !in_flight.answered {
+            let deadline = in_flight.deadline.unwrap_or_else(|| Instant::now() + self.config.response_timeout);
+            loop {
+                let message = match timeout_at(deadline.into(), self.next_message()).await {
+                    Ok(Ok(message)) => message,
+                    Ok(Err(Error::Decode(_))) => continue,
+                    Ok(Err(error)) => {
+                        self.in_flight = None;
+                        return Err(error);
+                    },
+                    Err(_) => break,
+                };
+                if message.address() != in_flight.address {
+                    continue;
+                }
+                match message {
+                    Message::Response(r) if in_flight.expected_length.is_some() => {
+                        let reply = if in_flight.expected_length == Some(r.data.len()) {
+                            Message::Ack(in_flight.address)
+                        } else {
+                            Message::Nak(in_flight.address)
+                        };
+                        self.writer.send(reply).await?;
+                        self.writer.flush().await?;
+                        break;
+                    },
+                    Message::Response(r) => self.forward_on_demand(r),
+                    Message::Ack(_) | Message::NakWithError(_) => break,
+                    _ => {},
+                }
+            }
+        }
+        self.in_flight = None;
+        Ok(())
+    }
+
+    /// Decodes a frame from the read buffer without waiting for the transport,
+    /// `at_end` when no more bytes are expected.
+    fn decode_buffered(&mut self, at_end: bool) -> Result<Option<Message>, DecodeError> {
+        let mut buf = std::mem::take(self.reader.read_buffer_mut());
+        let codec = self.reader.decoder_mut();
+        let result = if at_end { codec.decode_eof(&mut buf) } else { codec.decode(&mut buf) };
+        *self.reader.read_buffer_mut() = buf;
+        result
+    }
+
+    /// The next frame on the line. The end of the stream is reported as
+    /// [`io::ErrorKind::UnexpectedEof`].
+    async fn next_message(&mut self) -> Result<Message, Error> {
+        loop {
+            if self.decode_failed {
+                // after an error FramedRead waits for new bytes before it decodes
+                // again, frames already in its buffer come first
+                if let Some(message) = self.decode_buffered(false)? {
+                    return Ok(message);
+                }
+            }
+            let codec = self.reader.decoder();
+            let settles = codec.format == ProtocolFormat::Format1 && codec.sum_check == SumCheck::Tolerant;
+            let next = if settles {
+                match timeout(self.config.sum_check_wait, self.reader.next()).await {
+                    Ok(next) => next,
+                    // nothing follows an ETX, so no sum check either
+                    Err(_) => match self.decode_buffered(true)? {
+                        Some(message) => return Ok(message),
+                        None => continue,
+                    },
+                }
+            } else {
+                self.reader.next().await
+            };
+            match next {
+                Some(Ok(message)) => return Ok(message),
+                Some(Err(error)) => {
+                    self.decode_failed = true;
+                    return Err(error.into());
+                },
+                // FramedRead ends the stream once after an error but carries on reading afterwards
+                None if self.decode_failed => self.decode_failed = false,
+                None => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+            }
+        }
+    }
+}
+
+/// Head device of the chunk `words` registers after `head_device`. Bit
+/// devices hold 16 points per word.
+fn chunk_head(head_device: Device, words: usize) -> Result<Device, Error> {
+    let points = if head_device.kind.is_bit() { words * 16 } else { words };
+    Ok(head_device.offset(points as u32).map_err(invalid_input)?)
+}
+
+fn unexpected_data(data: ResponseData) -> Error {
+    Error::Protocol(format!("response data {:?} doesn't fit the command", data))
+}
+
+fn nak(n: NakWithError) -> Error {
+    Error::Nak { station: n.address().station, error_code: n.error_code() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Address, DecodeError};
+    use tokio::io::{duplex, AsyncWriteExt, DuplexStream};
+    use tokio_util::codec::Framed;
+
+    /// A client and the PLC end of the line it talks over.
+    fn connect() -> (Client<DuplexStream>, Framed<DuplexStream, FxCodec>) {
+        let (client, plc) = duplex(1024);
+        (Client::new(5, 0xFF, client), Framed::new(plc, FxCodec::new()))
+    }
+
+    async fn expect_request(plc: &mut Framed<DuplexStream, FxCodec>) -> Request {
+        match plc.next().await {
+            Some(Ok(Message::Request(request))) => request,
+            other => panic!("expected a request, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn read_over_duplex() {
+        let (mut client, mut plc) = connect();
+        let device: Device = "D106".parse().unwrap();
+        let station = tokio::spawn(async move {
+            let request = expect_request(&mut plc).await;
+            assert!(matches!(request.command, Command::ReadWords(_)));
+            plc.send(Message::Response(Response::new(request.address, "FFFE".to_string()))).await.unwrap();
+            assert!(matches!(plc.next().await, Some(Ok(Message::Ack(_)))));
+        });
+        assert_eq!(client.read_i16(device).await.unwrap(), -2);
+        station.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn write_forwards_on_demand_data() {
+        let (mut client, mut plc) = connect();
+        let mut events = client.on_demand_events();
+        let station = tokio::spawn(async move {
+            let request = expect_request(&mut plc).await;
+            assert!(matches!(request.command, Command::WriteBits(_)));
+            plc.send(Message::Response(Response::new(request.address, "1234".to_string()))).await.unwrap();
+            plc.send(Message::Ack(request.address)).await.unwrap();
+        });
+        client.set_bit("M10".parse().unwrap(), true).await.unwrap();
+        assert_eq!(events.recv().await.unwrap().data, "1234");
+        station.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn nak_and_decode_errors_are_returned() {
+        let (mut client, mut plc) = connect();
+        client.set_config(ClientConfig { retries: 0, ..ClientConfig::default() });
+        let device: Device = "D106".parse().unwrap();
+        let station = tokio::spawn(async move {
+            let request = expect_request(&mut plc).await;
+            plc.send(Message::NakWithError(NakWithError::new(request.address, 0x02))).await.unwrap();
+            expect_request(&mut plc).await;
+            // response with a broken sum check
+            plc.get_mut().write_all(b"\x0205FFFFFE\x0300\r\n").await.unwrap();
+            // the length fits the request but the data isn't hex
+            let request = expect_request(&mut plc).await;
+            plc.send(Message::Response(Response::new(request.address, "FFGE"))).await.unwrap();
+            assert!(matches!(plc.next().await, Some(Ok(Message::Nak(_)))));
+            // one word in answer to a read of two
+            let request = expect_request(&mut plc).await;
+            plc.send(Message::Response(Response::new(request.address, "0001"))).await.unwrap();
+            assert!(matches!(plc.next().await, Some(Ok(Message::Nak(_)))));
+            let request = expect_request(&mut plc).await;
+            plc.send(Message::Ack(request.address)).await.unwrap();
+        });
+        assert!(matches!(client.write_i16(device, 1).await, Err(Error::Nak { station: 5, error_code: NakErrorCode::SumCheck })));
+        assert!(matches!(client.read_i16(device).await, Err(Error::Decode(DecodeError::ChecksumMismatch { .. }))));
+        assert!(matches!(client.read_i16(device).await, Err(Error::Decode(DecodeError::BadHex { offset: 5, .. }))));
+        let short = client.read_i32(device).await;
+        assert!(matches!(short, Err(Error::Decode(DecodeError::DataLength { expected: 8, actual: 4 }))));
+        client.write_i16(device, 1).await.unwrap();
+        station.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn retry_after_timeout_ignores_late_answer() {
+        let (mut client, mut plc) = connect();
+        client.set_config(ClientConfig {
+            response_timeout: Duration::from_millis(100),
+            retries: 1,
+            retry_backoff: Duration::from_millis(200),
+            ..ClientConfig::default()
+        });
+        let device: Device = "D106".parse().unwrap();
+        let station = tokio::spawn(async move {
+            let request = expect_request(&mut plc).await;
+            tokio::time::sleep(Duration::from_millis(150)).await;
+            plc.send(Message::Response(Response::new(request.address, "0001".to_string()))).await.unwrap();
+            let request = expect_request(&mut plc).await;
+            plc.send(Message::Response(Response::new(request.address, "0002".to_string()))).await.unwrap();
+            assert!(matches!(plc.next().await, Some(Ok(Message::Ack(_)))));
+            plc
+        });
+        assert_eq!(client.read_i16(device).await.unwrap(), 2);
+        assert_eq!(client.stats().retries, 1);
+        assert_eq!(client.stats().timeouts, 1);
+        let _plc = station.await.unwrap();
+
+        client.set_config(ClientConfig { retries: 0, ..client.config().clone() });
+        assert!(matches!(client.read_i16(device).await, Err(Error::Timeout)));
+    }
+
+    #[tokio::test]
+    async fn late_answer_to_the_last_attempt_is_dropped() {
+        let (mut client, mut plc) = connect();
+        client.set_config(ClientConfig {
+            response_timeout: Duration::from_millis(100),
+            retries: 0,
+            retry_backoff: Duration::from_millis(200),
+            ..ClientConfig::default()
+        });
+        let device: Device = "D106".parse().unwrap();
+        let station = tokio::spawn(async move {
+            let request = expect_request(&mut plc).await;
+            tokio::time::sleep(Duration::from_millis(150)).await;
+            plc.send(Message::Response(Response::new(request.address, "0001"))).await.unwrap();
+            let request = expect_request(&mut plc).await;
+            plc.send(Message::Response(Response::new(request.address, "0002"))).await.unwrap();
+            assert!(matches!(plc.next().await, Some(Ok(Message::Ack(_)))));
+        });
+        assert!(matches!(client.read_i16(device).await, Err(Error::Timeout)));
+        assert_eq!(client.read_i16(device).await.unwrap(), 2);
+        station.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn loopback_times_the_answered_attempt() {
+        let (mut client, mut plc) = connect();
+        client.set_config(ClientConfig { response_timeout: Duration::from_millis(100), retries: 1, ..ClientConfig::default() });
+        let station = tokio::spawn(async move {
+            // the first attempt goes unanswered
+            expect_request(&mut plc).await;
+            let request = expect_request(&mut plc).await;
+            plc.send(Message::Response(Response::new(request.address, "03ABC"))).await.unwrap();
+            assert!(matches!(plc.next().await, Some(Ok(Message::Ack(_)))));
+            // an echo one character short
+            let request = expect_request(&mut plc).await;
+            plc.send(Message::Response(Response::new(request.address, "02AB"))).await.unwrap();
+            assert!(matches!(plc.next().await, Some(Ok(Message::Ack(_)))));
+        });
+        assert!(client.loopback("ABC").await.unwrap() < Duration::from_millis(100));
+        assert!(matches!(client.loopback("ABC").await, Err(Error::Protocol(_))));
+        station.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn tolerant_format1_takes_a_frame_without_sum_check() {
+        let (line, plc) = duplex(1024);
+        let mut client = Client::new(5, 0xFF, line);
+        client.set_protocol_format(ProtocolFormat::Format1);
+        client.set_sum_check(SumCheck::Tolerant);
+        let mut plc = Framed::new(plc, FxCodec::new().with_format(ProtocolFormat::Format1).with_sum_check(SumCheck::Disabled));
+        let device: Device = "D106".parse().unwrap();
+        let station = tokio::spawn(async move {
+            for data in ["0001", "0002"] {
+                let request = expect_request(&mut plc).await;
+                // the answer is the last frame on the line until it is acknowledged
+                plc.send(Message::Response(Response::new(request.address, data))).await.unwrap();
+                assert!(matches!(plc.next().await, Some(Ok(Message::Ack(_)))));
+            }
+        });
+        assert_eq!(client.read_i16(device).await.unwrap(), 1);
+        assert_eq!(client.read_i16(device).await.unwrap(), 2);
+        assert_eq!(client.stats().retries, 0);
+        station.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn retry_after_partial_frame() {
+        let (mut client, mut plc) = connect();
+        client.set_config(ClientConfig { response_timeout: Duration::from_millis(100), retries: 1, ..ClientConfig::default() });
+        let device: Device = "D106".parse().unwrap();
+        let station = tokio::spawn(async move {
+            expect_request(&mut plc).await;
+            // the line breaks off in the middle of an answer
+            plc.get_mut().write_all(b"\x0205FF0000123").await.unwrap();
+            let request = expect_request(&mut plc).await;
+            plc.send(Message::Ack(request.address)).await.unwrap();
+        });
+        client.write_i16(device, 1).await.unwrap();
+        assert_eq!(client.stats().retries, 1);
+        station.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn answers_from_other_stations_are_dropped() {
+        let (mut client, mut plc) = connect();
+        let device: Device = "D106".parse().unwrap();
+        let station = tokio::spawn(async move {
+            let request = expect_request(&mut plc).await;
+            plc.send(Message::Response(Response::new(Address::new(6, 0xFF), "0001".to_string()))).await.unwrap();
+            plc.send(Message::Response(Response::new(request.address, "0002".to_string()))).await.unwrap();
+            assert!(matches!(plc.next().await, Some(Ok(Message::Ack(_)))));
+            let request = expect_request(&mut plc).await;
+            plc.send(Message::Ack(Address::new(5, 0x01))).await.unwrap();
+            plc.send(Message::Ack(request.address)).await.unwrap();
+            expect_request(&mut plc).await;
+            plc.send(Message::Ack(Address::new(7, 0xFF))).await.unwrap();
+            // the answer of our station follows, it is acknowledged before the next request goes out
+            plc.send(Message::Ack(request.address)).await.unwrap();
+            let request = expect_request(&mut plc).await;
+            plc.send(Message::Response(Response::new(Address::new(7, 0xFF), "0001"))).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            plc.send(Message::Response(Response::new(request.address, "0001"))).await.unwrap();
+            assert!(matches!(plc.next().await, Some(Ok(Message::Ack(_)))));
+            let request = expect_request(&mut plc).await;
+            plc.send(Message::Ack(request.address)).await.unwrap();
+        });
+        assert_eq!(client.read_i16(device).await.unwrap(), 2);
+        client.write_i16(device, 1).await.unwrap();
+        assert_eq!(client.stats().mismatched_responses, 1);
+        assert_eq!(client.stats().mismatched_acks, 1);
+
+        client.set_config(ClientConfig { address_mismatch_is_error: true, ..ClientConfig::default() });
+        match client.write_i16(device, 1).await {
+            Err(Error::AddressMismatch { expected, actual }) => {
+                assert_eq!(expected, Address::new(5, 0xFF));
+                assert_eq!(actual, Address::new(7, 0xFF));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(client.stats().mismatched_frames(), 3);
+        assert!(matches!(client.read_i16(device).await, Err(Error::AddressMismatch { .. })));
+        client.set_config(ClientConfig::default());
+        client.write_i16(device, 2).await.unwrap();
+        station.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn dropped_request_is_finished_by_the_next_one() {
+        let (mut client, mut plc) = connect();
+        let device: Device = "D106".parse().unwrap();
+        let station = tokio::spawn(async move {
+            let request = expect_request(&mut plc).await;
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            plc.send(Message::Response(Response::new(request.address, "0001".to_string()))).await.unwrap();
+            // the orphaned answer is acknowledged before the next request
+            assert!(matches!(plc.next().await, Some(Ok(Message::Ack(_)))));
+            let request = expect_request(&mut plc).await;
+            plc.send(Message::Response(Response::new(request.address, "0002".to_string()))).await.unwrap();
+            assert!(matches!(plc.next().await, Some(Ok(Message::Ack(_)))));
+
+            // a garbled frame and the orphaned answer arrive in one read
+            expect_request(&mut plc).await;
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            plc.get_mut().write_all(b"\x0205FF\r\n\x0205FF0001\x03B5\r\n").await.unwrap();
+            assert!(matches!(plc.next().await, Some(Ok(Message::Ack(_)))));
+            let request = expect_request(&mut plc).await;
+            plc.send(Message::Response(Response::new(request.address, "0002"))).await.unwrap();
+            assert!(matches!(plc.next().await, Some(Ok(Message::Ack(_)))));
+        });
+        assert!(tokio::time::timeout(Duration::from_millis(10), client.read_i16(device)).await.is_err());
+        assert_eq!(client.read_i16(device).await.unwrap(), 2);
+        assert!(tokio::time::timeout(Duration::from_millis(10), client.read_i16(device)).await.is_err());
+        assert_eq!(client.read_i16(device).await.unwrap(), 2);
+        station.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn request_dropped_during_resync_acknowledges_late_answer() {
+        let (mut client, mut plc) = connect();
+        client.set_config(ClientConfig {
+            response_timeout: Duration::from_millis(50),
+            retry_backoff: Duration::from_millis(200),
+            ..ClientConfig::default()
+        });
+        let mut events = client.on_demand_events();
+        let device: Device = "D106".parse().unwrap();
+        let station = tokio::spawn(async move {
+            let request = expect_request(&mut plc).await;
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            plc.send(Message::Response(Response::new(request.address, "0001"))).await.unwrap();
+            assert!(matches!(plc.next().await, Some(Ok(Message::Ack(_)))));
+            let request = expect_request(&mut plc).await;
+            plc.send(Message::Response(Response::new(request.address, "0002"))).await.unwrap();
+            assert!(matches!(plc.next().await, Some(Ok(Message::Ack(_)))));
+        });
+        // given up while the client waits out the backoff after the timeout
+        assert!(tokio::time::timeout(Duration::from_millis(80), client.read_i16(device)).await.is_err());
+        assert_eq!(client.read_i16(device).await.unwrap(), 2);
+        assert!(events.try_recv().is_err());
+        station.await.unwrap();
+    }
+
+    /// Answers WR with the device numbers and records the number of points of each request.
+    async fn word_station(mut plc: Framed<DuplexStream, FxCodec>, points: mpsc::UnboundedSender<u8>) {
+        while let Some(Ok(message)) = plc.next().await {
+            let Message::Request(request) = message else { continue };
+            match &request.command {
+                Command::ReadWords(c) => {
+                    points.send(c.number_of_device_points).unwrap();
+                    let data: Vec<u16> = (0..c.number_of_device_points as u32).map(|i| (c.head_device.number + i) as u16).collect();
+                    plc.send(Message::Response(Response::from_words(request.address, &data))).await.unwrap();
+                }
+                Command::WriteWords(c) => {
+                    points.send(c.number_of_device_points).unwrap();
+                    let expected: Vec<u16> = (0..c.number_of_device_points as u32).map(|i| (c.head_device.number + i) as u16).collect();
+                    assert_eq!(c.data, expected);
+                    plc.send(Message::Ack(request.address)).await.unwrap();
+                }
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn batch_words_are_split_by_limits() {
+        let (mut client, plc) = connect();
+        let (tx, mut points) = mpsc::unbounded_channel();
+        tokio::spawn(word_station(plc, tx));
+        let d0: Device = "D0".parse().unwrap();
+
+        let words = client.read_words(d0, 100).await.unwrap();
+        assert_eq!(words, (0..100).collect::<Vec<u16>>());
+        assert_eq!((points.recv().await, points.recv().await), (Some(64), Some(36)));
+
+        client.set_config(ClientConfig { plc_model: Some(PlcModel::Fx1S), ..ClientConfig::default() });
+        assert_eq!(client.read_words(d0, 30).await.unwrap().len(), 30);
+        let counts: Vec<_> = vec![points.recv().await, points.recv().await, points.recv().await];
+        assert_eq!(counts, [Some(13), Some(13), Some(4)]);
+
+        client.write_words(d0, &(0..25).collect::<Vec<u16>>()).await.unwrap();
+        let counts: Vec<_> = vec![points.recv().await, points.recv().await, points.recv().await];
+        assert_eq!(counts, [Some(11), Some(11), Some(3)]);
+
+        assert!(client.read_words(d0, 0).await.unwrap().is_empty());
+        assert!(points.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn bit_devices_move_16_points_per_word() {
+        let (mut client, mut plc) = connect();
+        let station = tokio::spawn(async move {
+            let requests = [("M0", 32), ("M512", 32), ("M1024", 6), ("M0", 13), ("M208", 2), ("S0", 10), ("S160", 5)];
+            for (head, points) in requests {
+                let request = expect_request(&mut plc).await;
+                match &request.command {
+                    Command::ReadWords(c) => {
+                        assert_eq!((c.head_device, c.number_of_device_points), (head.parse().unwrap(), points));
+                        plc.send(Message::Response(Response::from_words(request.address, &vec![0; points as usize]))).await.unwrap();
+                        assert!(matches!(plc.next().await, Some(Ok(Message::Ack(_)))));
+                    }
+                    Command::WriteWords(c) => {
+                        assert_eq!((c.head_device, c.number_of_device_points), (head.parse().unwrap(), points));
+                        plc.send(Message::Ack(request.address)).await.unwrap();
+                    }
+                    other => panic!("unexpected {:?}", other),
+                }
+            }
+        });
+        assert_eq!(client.read_words("M0".parse().unwrap(), 70).await.unwrap().len(), 70);
+        client.set_config(ClientConfig { plc_model: Some(PlcModel::Fx1S), ..ClientConfig::default() });
+        assert_eq!(client.read_words("M0".parse().unwrap(), 15).await.unwrap().len(), 15);
+        client.write_words("S0".parse().unwrap(), &[0; 15]).await.unwrap();
+        station.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn i32_low_word_first() {
+        let (mut client, mut plc) = connect();
+        let device: Device = "D100".parse().unwrap();
+        let station = tokio::spawn(async move {
+            let request = expect_request(&mut plc).await;
+            // D100 = H5678, D101 = H1234
+            plc.send(Message::Response(Response::new(request.address, "56781234".to_string()))).await.unwrap();
+            assert!(matches!(plc.next().await, Some(Ok(Message::Ack(_)))));
+            for expected in [[0x86A0, 0x0001], [0x0001, 0x86A0]] {
+                let request = expect_request(&mut plc).await;
+                match &request.command {
+                    Command::WriteWords(c) => assert_eq!(c.data, expected),
+                    other => panic!("unexpected {:?}", other),
+                }
+                plc.send(Message::Ack(request.address)).await.unwrap();
+            }
+        });
+        assert_eq!(client.read_i32(device).await.unwrap(), 0x1234_5678);
+        client.write_i32(device, 100000).await.unwrap();
+        client.set_config(ClientConfig { word_order: WordOrder::HighFirst, ..ClientConfig::default() });
+        client.write_i32(device, 100000).await.unwrap();
+        station.await.unwrap();
+    }
+}
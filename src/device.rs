@@ -0,0 +1,219 @@
+use std::{fmt, str::FromStr};
+
+/// Device types that can be accessed over the computer link.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum DeviceKind {
+    /// Input, numbered in octal.
+    X,
+    /// Output, numbered in octal.
+    Y,
+    /// Auxiliary relay, M8000 and up are special relays.
+    M,
+    /// State relay.
+    S,
+    /// Timer contact.
+    TS,
+    /// Counter contact.
+    CS,
+    /// Timer current value.
+    TN,
+    /// Counter current value.
+    CN,
+    /// Data register, D8000 and up are special registers.
+    D,
+    /// Extension register.
+    R,
+}
+
+impl DeviceKind {
+    fn prefix(&self) -> &'static str {
+        match self {
+            DeviceKind::X => "X",
+            DeviceKind::Y => "Y",
+            DeviceKind::M => "M",
+            DeviceKind::S => "S",
+            DeviceKind::TS => "TS",
+            DeviceKind::CS => "CS",
+            DeviceKind::TN => "TN",
+            DeviceKind::CN => "CN",
+            DeviceKind::D => "D",
+            DeviceKind::R => "R",
+        }
+    }
+
+    fn radix(&self) -> u32 {
+        match self {
+            DeviceKind::X | DeviceKind::Y => 8,
+            _ => 10,
+        }
+    }
+
+    /// Device numbers that exist on the largest FX models.
+    fn is_valid(&self, number: u32) -> bool {
+        match self {
+            DeviceKind::X | DeviceKind::Y => number <= 0o377,
+            DeviceKind::M => number <= 7679 || (8000..=8511).contains(&number),
+            DeviceKind::S => number <= 4095,
+            DeviceKind::TS | DeviceKind::TN => number <= 511,
+            DeviceKind::CS | DeviceKind::CN => number <= 255,
+            DeviceKind::D => number <= 7999 || (8000..=8511).contains(&number),
+            DeviceKind::R => number <= 32767,
+        }
+    }
+
+    /// Bit devices are addressed per point, word devices per 16 bit register.
+    pub fn is_bit(&self) -> bool {
+        matches!(self, DeviceKind::X | DeviceKind::Y | DeviceKind::M | DeviceKind::S | DeviceKind::TS | DeviceKind::CS)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceError {
+    UnknownKind(String),
+    /// Missing digits or digits not valid for the device, like 8 or 9 for X and Y.
+    InvalidNumber(String),
+    OutOfRange { kind: DeviceKind, number: u32 },
+    /// The device number does not fit into a device field of `width` characters.
+    TooWide { device: Device, width: usize },
+}
+
+impl fmt::Display for DeviceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceError::UnknownKind(s) => write!(f, "unknown device type in {:?}", s),
+            DeviceError::InvalidNumber(s) => write!(f, "invalid device number in {:?}", s),
+            DeviceError::OutOfRange { kind, number } => {
+                write!(f, "device number {} out of range for {}", number, kind.prefix())
+            }
+            DeviceError::TooWide { device, width } => {
+                write!(f, "device {} does not fit into {} characters", device, width)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DeviceError {}
+
+/// A PLC device like `D106` or `X17`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Device {
+    pub kind: DeviceKind,
+    pub number: u32,
+}
+
+impl Device {
+    pub fn new(kind: DeviceKind, number: u32) -> Result<Self, DeviceError> {
+        if !kind.is_valid(number) {
+            return Err(DeviceError::OutOfRange { kind, number });
+        }
+        Ok(Device { kind, number })
+    }
+
+    /// M8000 and up or D8000 and up.
+    pub fn is_special(&self) -> bool {
+        matches!(self.kind, DeviceKind::M | DeviceKind::D) && self.number >= 8000
+    }
+
+    /// The device `offset` points further, for example `D110` for `D106` and 4.
+    pub fn offset(&self, offset: u32) -> Result<Device, DeviceError> {
+        Device::new(self.kind, self.number.saturating_add(offset))
+    }
+
+    /// The 5 character device field used by the regular commands, e.g. `D0106`.
+    pub fn code(&self) -> Result<String, DeviceError> {
+        self.code_with_width(5)
+    }
+
+    /// The 7 character device field used by the extended Q commands, e.g. `D000106`.
+    pub fn extended_code(&self) -> Result<String, DeviceError> {
+        self.code_with_width(7)
+    }
+
+    fn code_with_width(&self, width: usize) -> Result<String, DeviceError> {
+        let prefix = self.kind.prefix();
+        let digits = width - prefix.len();
+        let number = match self.kind.radix() {
+            8 => format!("{:0digits$o}", self.number),
+            _ => format!("{:0digits$}", self.number),
+        };
+        if number.len() > digits {
+            return Err(DeviceError::TooWide { device: *self, width });
+        }
+        Ok(format!("{}{}", prefix, number))
+    }
+}
+
+impl fmt::Display for Device {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind.radix() {
+            8 => write!(f, "{}{:o}", self.kind.prefix(), self.number),
+            _ => write!(f, "{}{}", self.kind.prefix(), self.number),
+        }
+    }
+}
+
+impl FromStr for Device {
+    type Err = DeviceError;
+
+    /// Accepts the written form (`D106`, `X17`) as well as the padded wire
+    /// form (`D0106`). `T` and `C` are shorthand for the contacts `TS` and `CS`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let split = s.find(|c: char| c.is_ascii_digit()).unwrap_or(s.len());
+        let (prefix, digits) = s.split_at(split);
+        let kind = match prefix.to_ascii_uppercase().as_str() {
+            "X" => DeviceKind::X,
+            "Y" => DeviceKind::Y,
+            "M" => DeviceKind::M,
+            "S" => DeviceKind::S,
+            "T" | "TS" => DeviceKind::TS,
+            "C" | "CS" => DeviceKind::CS,
+            "TN" => DeviceKind::TN,
+            "CN" => DeviceKind::CN,
+            "D" => DeviceKind::D,
+            "R" => DeviceKind::R,
+            _ => return Err(DeviceError::UnknownKind(s.to_string())),
+        };
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(DeviceError::InvalidNumber(s.to_string()));
+        }
+        let number = u32::from_str_radix(digits, kind.radix())
+            .map_err(|_| DeviceError::InvalidNumber(s.to_string()))?;
+        Device::new(kind, number)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_devices() {
+        assert_eq!("D106".parse(), Ok(Device { kind: DeviceKind::D, number: 106 }));
+        assert_eq!("D0106".parse(), Ok(Device { kind: DeviceKind::D, number: 106 }));
+        assert_eq!("X17".parse(), Ok(Device { kind: DeviceKind::X, number: 0o17 }));
+        assert_eq!("T10".parse(), Ok(Device { kind: DeviceKind::TS, number: 10 }));
+        assert_eq!("CN200".parse(), Ok(Device { kind: DeviceKind::CN, number: 200 }));
+        assert!("M8002".parse::<Device>().unwrap().is_special());
+
+        assert!(matches!("X18".parse::<Device>(), Err(DeviceError::InvalidNumber(_))));
+        assert!(matches!("Q10".parse::<Device>(), Err(DeviceError::UnknownKind(_))));
+        assert!(matches!("D".parse::<Device>(), Err(DeviceError::InvalidNumber(_))));
+        assert!(matches!("D-1".parse::<Device>(), Err(DeviceError::UnknownKind(_))));
+        assert!(matches!("D8600".parse::<Device>(), Err(DeviceError::OutOfRange { .. })));
+        assert!(matches!("TN512".parse::<Device>(), Err(DeviceError::OutOfRange { .. })));
+    }
+
+    #[test]
+    fn device_codes() {
+        let device: Device = "D106".parse().unwrap();
+        assert_eq!(device.code().unwrap(), "D0106");
+        assert_eq!(device.extended_code().unwrap(), "D000106");
+        assert_eq!(device.to_string(), "D106");
+        let device: Device = "Y17".parse().unwrap();
+        assert_eq!(device.code().unwrap(), "Y0017");
+        assert_eq!(device.to_string(), "Y17");
+        assert_eq!("TN10".parse::<Device>().unwrap().code().unwrap(), "TN010");
+        assert_eq!("R32767".parse::<Device>().unwrap().extended_code().unwrap(), "R032767");
+        assert!(matches!("R32767".parse::<Device>().unwrap().code(), Err(DeviceError::TooWide { width: 5, .. })));
+    }
+}
@@ -0,0 +1,146 @@
+use std::{fmt, io};
+
+use crate::{Address, DeviceError, Message, NakErrorCode};
+
+/// Errors produced by [`FxCodec`](crate::FxCodec) while decoding a frame.
+///
+/// Offsets are counted from the control character (STX, ENQ, ACK or NAK)
+/// that starts the frame.
+#[derive(Debug)]
+pub enum DecodeError {
+    Io(io::Error),
+    /// The frame ended before all required characters were received.
+    Truncated { needed: usize, actual: usize },
+    /// ACK or NAK frame with a length the protocol does not define.
+    InvalidLength { frame: &'static str, length: usize },
+    /// A field that must be hexadecimal contains other characters.
+    BadHex { field: &'static str, offset: usize },
+    /// A data character outside of the ASCII range.
+    NonAscii { offset: usize },
+    /// A bit data character other than `0` or `1`.
+    BadBit { offset: usize },
+    /// STX frame without ETX in front of the sum check.
+    MissingEtx,
+    /// `expected` is the sum calculated over the frame, `actual` the one sent in it.
+    ChecksumMismatch { expected: u8, actual: u8 },
+    UnknownControlByte(u8),
+    UnknownCommand(String),
+    InvalidDevice { offset: usize, error: DeviceError },
+    /// Length of the data does not match the number of device points.
+    DataLength { expected: usize, actual: usize },
+    /// STX response data for a command that is answered with ACK.
+    NoResponseData(String),
+    LineTooLong,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Io(e) => write!(f, "io error: {}", e),
+            DecodeError::Truncated { needed, actual } => {
+                write!(f, "truncated frame: needed {} characters, got {}", needed, actual)
+            }
+            DecodeError::InvalidLength { frame, length } => {
+                write!(f, "invalid {} frame length {}", frame, length)
+            }
+            DecodeError::BadHex { field, offset } => {
+                write!(f, "invalid hex in {} at offset {}", field, offset)
+            }
+            DecodeError::BadBit { offset } => write!(f, "invalid bit value at offset {}", offset),
+            DecodeError::NonAscii { offset } => write!(f, "non ASCII character at offset {}", offset),
+            DecodeError::MissingEtx => write!(f, "ETX not found at expected position"),
+            DecodeError::ChecksumMismatch { expected, actual } => {
+                write!(f, "checksum mismatch: calculated {:02X}, received {:02X}", expected, actual)
+            }
+            DecodeError::UnknownControlByte(b) => write!(f, "unknown control byte 0x{:02X}", b),
+            DecodeError::UnknownCommand(c) => write!(f, "command {} not implemented", c),
+            DecodeError::InvalidDevice { offset, error } => {
+                write!(f, "invalid device at offset {}: {}", offset, error)
+            }
+            DecodeError::DataLength { expected, actual } => {
+                write!(f, "data length not correct: expected {}, got {}", expected, actual)
+            }
+            DecodeError::NoResponseData(c) => write!(f, "command {} has no response data", c),
+            DecodeError::LineTooLong => write!(f, "line length limit exceeded"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DecodeError::Io(e) => Some(e),
+            DecodeError::InvalidDevice { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for DecodeError {
+    fn from(e: io::Error) -> Self {
+        DecodeError::Io(e)
+    }
+}
+
+/// Errors returned by [`Client`](crate::Client).
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    /// The station did not answer in time.
+    Timeout,
+    /// The station rejected the request with a NAK frame.
+    Nak { station: u8, error_code: NakErrorCode },
+    /// A valid frame that is not an answer to the pending request.
+    UnexpectedMessage(Message),
+    /// An answer from another station or PLC number than the one addressed.
+    AddressMismatch { expected: Address, actual: Address },
+    /// The received bytes could not be decoded into a frame.
+    Decode(DecodeError),
+    /// A frame that decodes but whose content does not fit the request.
+    Protocol(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "io error: {}", e),
+            Error::Timeout => write!(f, "no answer from station"),
+            Error::Nak { station, error_code } => {
+                write!(f, "station {:02X} answered with NAK: {}", station, error_code)
+            }
+            Error::UnexpectedMessage(message) => write!(f, "unexpected message: {:?}", message),
+            Error::AddressMismatch { expected, actual } => write!(
+                f,
+                "answer from station {:02X} PLC {:02X}, expected station {:02X} PLC {:02X}",
+                actual.station, actual.plc, expected.station, expected.plc
+            ),
+            Error::Decode(e) => write!(f, "decode error: {}", e),
+            Error::Protocol(message) => write!(f, "protocol error: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Decode(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<DecodeError> for Error {
+    fn from(e: DecodeError) -> Self {
+        match e {
+            DecodeError::Io(e) => Error::Io(e),
+            e => Error::Decode(e),
+        }
+    }
+}
@@ -0,0 +1,260 @@
+use std::collections::VecDeque;
+use std::io;
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::{mpsc, oneshot};
+use tokio_serial::SerialStream;
+
+use crate::{Client, Device, Error, PlcModel, PlcValue, ScatteredValue};
+
+/// Order in which queued requests of a [`ClientHandle`] go out. Requests of
+/// the same priority keep the order they were made in.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub enum Priority {
+    /// Alarms and operator commands.
+    High,
+    #[default]
+    Normal,
+    /// Background polling.
+    Low,
+}
+
+impl Priority {
+    fn index(&self) -> usize {
+        match self {
+            Priority::High => 0,
+            Priority::Normal => 1,
+            Priority::Low => 2,
+        }
+    }
+}
+
+type Job<T> = Box<dyn for<'a> FnOnce(&'a mut Client<T>) -> BoxFuture<'a, ()> + Send>;
+
+/// Shareable access to a [`Client`] owned by a background task.
+///
+/// Requests are queued and run one after the other by the task. A request
+/// that was started is run to its end even if the caller stops waiting for
+/// it, so the line is never left in the middle of a transaction. The task
+/// ends when the last handle is dropped and the queue is empty.
+pub struct ClientHandle<T = SerialStream> {
+    jobs: mpsc::UnboundedSender<(Priority, Job<T>)>,
+    priority: Priority,
+}
+
+impl<T> Clone for ClientHandle<T> {
+    fn clone(&self) -> Self {
+        ClientHandle {
+            jobs: self.jobs.clone(),
+            priority: self.priority,
+        }
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite + Send + 'static> ClientHandle<T> {
+    /// Moves `client` into a new task, must be called within a tokio runtime.
+    pub fn spawn(client: Client<T>) -> Self {
+        let (jobs, queue) = mpsc::unbounded_channel();
+        tokio::spawn(run(client, queue));
+        ClientHandle {
+            jobs,
+            priority: Priority::Normal,
+        }
+    }
+
+    /// A handle to the same client whose requests are queued with `priority`.
+    pub fn with_priority(&self, priority: Priority) -> Self {
+        ClientHandle {
+            jobs: self.jobs.clone(),
+            priority,
+        }
+    }
+
+    pub fn priority(&self) -> Priority {
+        self.priority
+    }
+
+    /// Runs `f` with exclusive access to the client, for requests that belong
+    /// together or methods the handle doesn't offer:
+    ///
+    /// ```ignore
+    /// let bits = handle.call(move |client| Box::pin(client.read_bits(device, 8))).await?;
+    /// ```
+    pub async fn call<R, F>(&self, f: F) -> Result<R, Error>
+    where
+        R: Send + 'static,
+        F: for<'a> FnOnce(&'a mut Client<T>) -> BoxFuture<'a, Result<R, Error>> + Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        let job: Job<T> = Box::new(move |client| {
+            Box::pin(async move {
+                // nobody to tell if the caller is gone
+                let _ = tx.send(f(client).await);
+            })
+        });
+        self.jobs.send((self.priority, job)).map_err(|_| stopped())?;
+        rx.await.map_err(|_| stopped())?
+    }
+
+    pub async fn read_i16(&self, head_device: Device) -> Result<i16, Error> {
+        self.call(move |client| Box::pin(client.read_i16(head_device))).await
+    }
+
+    pub async fn write_i16(&self, head_device: Device, value: i16) -> Result<(), Error> {
+        self.call(move |client| Box::pin(client.write_i16(head_device, value))).await
+    }
+
+    pub async fn read_i32(&self, head_device: Device) -> Result<i32, Error> {
+        self.call(move |client| Box::pin(client.read_i32(head_device))).await
+    }
+
+    pub async fn write_i32(&self, head_device: Device, value: i32) -> Result<(), Error> {
+        self.call(move |client| Box::pin(client.write_i32(head_device, value))).await
+    }
+
+    pub async fn read<V: PlcValue + Send + 'static>(&self, head_device: Device) -> Result<V, Error> {
+        self.call(move |client| Box::pin(client.read(head_device))).await
+    }
+
+    pub async fn write<V: PlcValue + Send + 'static>(&self, head_device: Device, value: V) -> Result<(), Error> {
+        self.call(move |client| Box::pin(client.write(head_device, value))).await
+    }
+
+    pub async fn read_words(&self, head_device: Device, count: usize) -> Result<Vec<u16>, Error> {
+        self.call(move |client| Box::pin(client.read_words(head_device, count))).await
+    }
+
+    pub async fn write_words(&self, head_device: Device, values: Vec<u16>) -> Result<(), Error> {
+        self.call(move |client| Box::pin(async move { client.write_words(head_device, &values).await })).await
+    }
+
+    pub async fn read_string(&self, head_device: Device, max_len: usize) -> Result<String, Error> {
+        self.call(move |client| Box::pin(client.read_string(head_device, max_len))).await
+    }
+
+    pub async fn write_string(&self, head_device: Device, text: String) -> Result<(), Error> {
+        self.call(move |client| Box::pin(async move { client.write_string(head_device, &text).await })).await
+    }
+
+    pub async fn read_bits(&self, head_device: Device, count: u16) -> Result<Vec<bool>, Error> {
+        self.call(move |client| Box::pin(client.read_bits(head_device, count))).await
+    }
+
+    pub async fn write_bits(&self, head_device: Device, values: Vec<bool>) -> Result<(), Error> {
+        self.call(move |client| Box::pin(async move { client.write_bits(head_device, &values).await })).await
+    }
+
+    pub async fn set_bit(&self, device: Device, value: bool) -> Result<(), Error> {
+        self.call(move |client| Box::pin(client.set_bit(device, value))).await
+    }
+
+    pub async fn write_scattered(&self, values: Vec<(Device, ScatteredValue)>) -> Result<(), Error> {
+        self.call(move |client| Box::pin(async move { client.write_scattered(&values).await })).await
+    }
+
+    pub async fn remote_run(&self) -> Result<(), Error> {
+        self.call(|client| Box::pin(client.remote_run())).await
+    }
+
+    pub async fn remote_stop(&self) -> Result<(), Error> {
+        self.call(|client| Box::pin(client.remote_stop())).await
+    }
+
+    pub async fn plc_model(&self) -> Result<PlcModel, Error> {
+        self.call(|client| Box::pin(client.plc_model())).await
+    }
+
+    pub async fn loopback(&self, payload: String) -> Result<Duration, Error> {
+        self.call(move |client| Box::pin(async move { client.loopback(&payload).await })).await
+    }
+
+    pub async fn global(&self, set: bool) -> Result<(), Error> {
+        self.call(move |client| Box::pin(client.global(set))).await
+    }
+}
+
+fn stopped() -> Error {
+    Error::Io(io::Error::new(io::ErrorKind::BrokenPipe, "client task stopped"))
+}
+
+/// Runs the queued jobs, highest priority first.
+async fn run<T>(mut client: Client<T>, mut queue: mpsc::UnboundedReceiver<(Priority, Job<T>)>) {
+    let mut pending: [VecDeque<Job<T>>; 3] = Default::default();
+    loop {
+        while let Ok((priority, job)) = queue.try_recv() {
+            pending[priority.index()].push_back(job);
+        }
+        match pending.iter_mut().find_map(VecDeque::pop_front) {
+            Some(job) => job(&mut client).await,
+            None => match queue.recv().await {
+                Some((priority, job)) => pending[priority.index()].push_back(job),
+                None => return,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FxCodec, Message, Response};
+    use futures::{SinkExt, StreamExt};
+    use tokio::io::duplex;
+    use tokio_util::codec::Framed;
+
+    #[tokio::test]
+    async fn queued_requests_by_priority() {
+        let (line, plc) = duplex(1024);
+        let handle = ClientHandle::spawn(Client::new(5, 0xFF, line));
+        let mut plc = Framed::new(plc, FxCodec::new());
+        let (log_tx, mut log) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Some(Ok(message)) = plc.next().await {
+                match message {
+                    Message::Request(request) => {
+                        let device = match &request.command {
+                            crate::Command::ReadWords(c) => c.head_device.number,
+                            other => panic!("unexpected {:?}", other),
+                        };
+                        log_tx.send(format!("D{}", device)).unwrap();
+                        tokio::time::sleep(Duration::from_millis(50)).await;
+                        plc.send(Message::Response(Response::new(request.address, format!("{:04X}", device)))).await.unwrap();
+                    }
+                    Message::Ack(_) => log_tx.send("ACK".to_string()).unwrap(),
+                    other => panic!("unexpected {:?}", other),
+                }
+            }
+        });
+        let d = |n: u32| Device::new(crate::DeviceKind::D, n).unwrap();
+
+        let busy = tokio::spawn({
+            let handle = handle.clone();
+            async move { handle.read_i16(d(1)).await }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let low = tokio::spawn({
+            let handle = handle.with_priority(Priority::Low);
+            async move { handle.read_i16(d(2)).await }
+        });
+        let high = tokio::spawn({
+            let handle = handle.with_priority(Priority::High);
+            async move { handle.read_i16(d(3)).await }
+        });
+        assert_eq!(busy.await.unwrap().unwrap(), 1);
+        assert_eq!(low.await.unwrap().unwrap(), 2);
+        assert_eq!(high.await.unwrap().unwrap(), 3);
+
+        // the caller gives up, the transaction is still finished
+        assert!(tokio::time::timeout(Duration::from_millis(10), handle.read_i16(d(4))).await.is_err());
+        assert_eq!(handle.read_i16(d(5)).await.unwrap(), 5);
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let mut entries = Vec::new();
+        while let Ok(entry) = log.try_recv() {
+            entries.push(entry);
+        }
+        assert_eq!(entries, ["D1", "ACK", "D3", "ACK", "D2", "ACK", "D4", "ACK", "D5", "ACK"]);
+    }
+}
@@ -1,18 +1,32 @@
 extern crate core;
 
 
-use bytes::{BufMut, BytesMut, Buf};
-use std::{cmp, io, str, usize};
-use futures::{SinkExt, StreamExt};
-use tokio::io::{ReadHalf, WriteHalf};
-use tokio_serial::SerialStream;
-
-// use tokio::io::{AsyncRead, AsyncWrite};
-use tokio_util::codec::{Encoder, Decoder, FramedRead, FramedWrite};
-use crate::Command::{ReadWords, WriteWords};
-
-
-#[derive(Debug, Copy, Clone)]
+mod bus;
+mod client;
+mod device;
+mod error;
+mod handle;
+mod plc_model;
+mod value;
+
+use bytes::{BufMut, Bytes, BytesMut, Buf};
+use std::{cmp, fmt, io};
+use tokio_util::codec::{Encoder, Decoder};
+use crate::Command::{
+    ExtendedReadWords, ExtendedTestWords, ExtendedWriteWords, Global, LoopbackTest, Raw, ReadBits, ReadPlcType,
+    ReadWords, RemoteRun, RemoteStop, TestBits, TestWords, WriteBits, WriteWords,
+};
+
+pub use crate::bus::{Bus, Station, StationGuard};
+pub use crate::client::{Client, ClientConfig, ClientStats, ScatteredValue};
+pub use crate::device::{Device, DeviceError, DeviceKind};
+pub use crate::error::{DecodeError, Error};
+pub use crate::handle::{ClientHandle, Priority};
+pub use crate::plc_model::PlcModel;
+pub use crate::value::{Bcd16, Bcd32, Fixed16, Fixed32, PlcValue, WordOrder};
+
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub struct Address {
     pub station: u8,
     pub plc: u8,
@@ -26,7 +40,7 @@ impl Address {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct NakWithError {
     address: Address,
     error_code: u8,
@@ -38,15 +52,103 @@ impl NakWithError {
             error_code
         }
     }
+
+    pub fn address(&self) -> Address {
+        self.address
+    }
+
+    pub fn error_code(&self) -> NakErrorCode {
+        NakErrorCode::from(self.error_code)
+    }
 }
 
-#[derive(Debug)]
+/// Error codes a station sends with NAK, as listed in the computer link manual.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum NakErrorCode {
+    /// Parity, overrun or framing error on the line.
+    Parity,
+    SumCheck,
+    /// The request does not follow the control procedure.
+    Protocol,
+    /// The device range of the request is not accessible.
+    CharacterArea,
+    /// A character that is not allowed in the request.
+    Character,
+    /// The PLC number is neither FF nor the number of the station.
+    PcNumber,
+    /// The request was not completed in time.
+    Timing,
+    /// Remote RUN or STOP not possible in the current state.
+    Remote,
+    Unknown(u8),
+}
+
+impl NakErrorCode {
+    pub fn code(&self) -> u8 {
+        match self {
+            NakErrorCode::Parity => 0x01,
+            NakErrorCode::SumCheck => 0x02,
+            NakErrorCode::Protocol => 0x03,
+            NakErrorCode::CharacterArea => 0x06,
+            NakErrorCode::Character => 0x07,
+            NakErrorCode::PcNumber => 0x0A,
+            NakErrorCode::Timing => 0x10,
+            NakErrorCode::Remote => 0x18,
+            NakErrorCode::Unknown(code) => *code,
+        }
+    }
+}
+
+impl From<u8> for NakErrorCode {
+    fn from(code: u8) -> Self {
+        match code {
+            0x01 => NakErrorCode::Parity,
+            0x02 => NakErrorCode::SumCheck,
+            0x03 => NakErrorCode::Protocol,
+            0x06 => NakErrorCode::CharacterArea,
+            0x07 => NakErrorCode::Character,
+            0x0A => NakErrorCode::PcNumber,
+            0x10 => NakErrorCode::Timing,
+            0x18 => NakErrorCode::Remote,
+            code => NakErrorCode::Unknown(code),
+        }
+    }
+}
+
+impl fmt::Display for NakErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NakErrorCode::Parity => write!(f, "parity error"),
+            NakErrorCode::SumCheck => write!(f, "sum check error"),
+            NakErrorCode::Protocol => write!(f, "protocol error"),
+            NakErrorCode::CharacterArea => write!(f, "character area error"),
+            NakErrorCode::Character => write!(f, "character error"),
+            NakErrorCode::PcNumber => write!(f, "PC number error"),
+            NakErrorCode::Timing => write!(f, "timing error"),
+            NakErrorCode::Remote => write!(f, "remote error"),
+            NakErrorCode::Unknown(code) => write!(f, "unknown error code {:02X}", code),
+        }
+    }
+}
+
+/// Most words a word unit batch command can address. Older CPUs allow
+/// less, see [`PlcModel::max_read_words`].
+pub const MAX_WORD_POINTS: u8 = 64;
+
+/// Most words of bit devices, 16 points each, a WR or QR command can read.
+/// Older CPUs allow less, see [`PlcModel::max_read_bit_words`].
+pub const MAX_READ_BIT_WORDS: u8 = 32;
+
+/// Most words of bit devices a WW or QW command can write.
+pub const MAX_WRITE_BIT_WORDS: u8 = 10;
+
+#[derive(Debug, Clone)]
 pub struct ReadWordsCommand {
-    pub head_device: String, //exact 5 long
+    pub head_device: Device,
     pub number_of_device_points: u8,
 }
 impl ReadWordsCommand {
-    pub fn new(head_device: String, number_of_device_points: u8) -> Self {
+    pub fn new(head_device: Device, number_of_device_points: u8) -> Self {
         ReadWordsCommand {
             head_device,
             number_of_device_points,
@@ -54,29 +156,215 @@ impl ReadWordsCommand {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct WriteWordsCommand {
-    pub head_device: String, //exact 5 long
+    pub head_device: Device,
     pub number_of_device_points: u8,
-    pub data: String,
+    /// One value per point.
+    pub data: Vec<u16>,
 }
 impl WriteWordsCommand {
-    pub fn new(head_device: String, number_of_device_points: u8, data: String) -> Self {
+    pub fn new(head_device: Device, data: Vec<u16>) -> Self {
         WriteWordsCommand {
+            head_device,
+            number_of_device_points: data.len() as u8,
+            data,
+        }
+    }
+}
+
+/// Most points a bit unit batch command can address, sent as `00`.
+pub const MAX_BIT_POINTS: u16 = 256;
+
+#[derive(Debug, Clone)]
+pub struct ReadBitsCommand {
+    pub head_device: Device,
+    pub number_of_device_points: u16, //1 to 256
+}
+impl ReadBitsCommand {
+    pub fn new(head_device: Device, number_of_device_points: u16) -> Self {
+        ReadBitsCommand {
             head_device,
             number_of_device_points,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WriteBitsCommand {
+    pub head_device: Device,
+    pub number_of_device_points: u16, //1 to 256
+    /// One value per point.
+    pub data: Vec<bool>,
+}
+impl WriteBitsCommand {
+    pub fn new(head_device: Device, data: Vec<bool>) -> Self {
+        WriteBitsCommand {
+            head_device,
+            number_of_device_points: data.len() as u16,
             data,
         }
     }
 }
 
-#[derive(Debug)]
+/// Most devices a BT command can set or reset.
+pub const MAX_TEST_BITS: usize = 20;
+/// Most registers a WT command can write.
+pub const MAX_TEST_WORDS: usize = 10;
+
+/// Sets or resets bit devices scattered over the device memory (BT).
+#[derive(Debug, Clone)]
+pub struct TestBitsCommand {
+    pub points: Vec<(Device, bool)>,
+}
+impl TestBitsCommand {
+    pub fn new(points: Vec<(Device, bool)>) -> Self {
+        TestBitsCommand {
+            points,
+        }
+    }
+}
+
+/// Writes word devices scattered over the device memory (WT).
+#[derive(Debug, Clone)]
+pub struct TestWordsCommand {
+    pub points: Vec<(Device, u16)>,
+}
+impl TestWordsCommand {
+    pub fn new(points: Vec<(Device, u16)>) -> Self {
+        TestWordsCommand {
+            points,
+        }
+    }
+}
+
+/// Station number a global command is sent to.
+pub const GLOBAL_STATION: u8 = 0xFF;
+
+/// Sets or resets M8126 in all stations at once (GW), no station replies.
+#[derive(Debug, Clone)]
+pub struct GlobalCommand {
+    pub set: bool,
+}
+impl GlobalCommand {
+    pub fn new(set: bool) -> Self {
+        GlobalCommand {
+            set,
+        }
+    }
+}
+
+/// Most characters a TT command can carry.
+pub const MAX_LOOPBACK_CHARACTERS: usize = 254;
+
+/// Characters the PLC sends back unchanged (TT).
+#[derive(Debug, Clone)]
+pub struct LoopbackTestCommand {
+    pub data: String,
+}
+impl LoopbackTestCommand {
+    pub fn new(data: String) -> Self {
+        LoopbackTestCommand {
+            data,
+        }
+    }
+}
+
+/// A command the crate has no type for, sent as is: `code` follows the PLC
+/// number, `body` the message wait time.
+#[derive(Debug, Clone)]
+pub struct RawCommand {
+    pub code: String,
+    pub body: String,
+}
+impl RawCommand {
+    pub fn new(code: String, body: String) -> Self {
+        RawCommand {
+            code,
+            body,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub enum Command {
     ReadWords(ReadWordsCommand),
     WriteWords(WriteWordsCommand),
+    ReadBits(ReadBitsCommand),
+    WriteBits(WriteBitsCommand),
+    TestBits(TestBitsCommand),
+    TestWords(TestWordsCommand),
+    RemoteRun,
+    RemoteStop,
+    ReadPlcType,
+    Global(GlobalCommand),
+    LoopbackTest(LoopbackTestCommand),
+    /// QR, like WR with a 7 character head device for the extended device range.
+    ExtendedReadWords(ReadWordsCommand),
+    /// QW, like WW with a 7 character head device.
+    ExtendedWriteWords(WriteWordsCommand),
+    /// QT, like WT with 7 character devices.
+    ExtendedTestWords(TestWordsCommand),
+    /// Escape hatch for commands without a variant, only encoded.
+    Raw(RawCommand),
 }
 
-#[derive(Debug)]
+impl Command {
+    /// Reads words with WR, or QR if the head device doesn't fit into WR's device field.
+    pub fn read_words(head_device: Device, number_of_device_points: u8) -> Command {
+        let command = ReadWordsCommand::new(head_device, number_of_device_points);
+        if head_device.code().is_ok() {
+            ReadWords(command)
+        } else {
+            ExtendedReadWords(command)
+        }
+    }
+
+    /// Writes words with WW, or QW if the head device doesn't fit into WW's device field.
+    pub fn write_words(head_device: Device, data: Vec<u16>) -> Command {
+        let command = WriteWordsCommand::new(head_device, data);
+        if head_device.code().is_ok() {
+            WriteWords(command)
+        } else {
+            ExtendedWriteWords(command)
+        }
+    }
+
+    /// The two character command code following the PLC number.
+    pub fn code(&self) -> &str {
+        match self {
+            ReadWords(_) => "WR",
+            WriteWords(_) => "WW",
+            ReadBits(_) => "BR",
+            WriteBits(_) => "BW",
+            TestBits(_) => "BT",
+            TestWords(_) => "WT",
+            RemoteRun => "RR",
+            RemoteStop => "RS",
+            ReadPlcType => "PC",
+            Global(_) => "GW",
+            LoopbackTest(_) => "TT",
+            ExtendedReadWords(_) => "QR",
+            ExtendedWriteWords(_) => "QW",
+            ExtendedTestWords(_) => "QT",
+            Raw(c) => &c.code,
+        }
+    }
+
+    /// Number of data characters in the STX response to this command, `None`
+    /// for commands answered with ACK.
+    pub fn response_length(&self) -> Option<usize> {
+        match self {
+            ReadWords(c) | ExtendedReadWords(c) => Some(c.number_of_device_points as usize * 4),
+            ReadBits(c) => Some(c.number_of_device_points as usize),
+            ReadPlcType => Some(2),
+            LoopbackTest(c) => Some(2 + c.data.len()),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Request {
     pub address: Address,
     pub command: Command,
@@ -93,21 +381,86 @@ impl Request {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Response {
     pub address: Address,
-    pub data: String,
+    /// The characters between STX and ETX as received. The frame doesn't
+    /// tell which command it answers, so the codec can't decode them. The
+    /// client does with [`decode`](Response::decode) and the pending command.
+    pub data: Bytes,
 }
 impl Response {
-    pub fn new(address: Address, data: String) -> Self {
+    pub fn new(address: Address, data: impl Into<Bytes>) -> Self {
         Response {
             address,
-            data,
+            data: data.into(),
         }
     }
+
+    /// The answer to WR or QR.
+    pub fn from_words(address: Address, words: &[u16]) -> Self {
+        let data: String = words.iter().map(|w| format!("{:04X}", w)).collect();
+        Response::new(address, data)
+    }
+
+    /// The answer to BR.
+    pub fn from_bits(address: Address, bits: &[bool]) -> Self {
+        let data: Vec<u8> = bits.iter().map(|b| if *b { b'1' } else { b'0' }).collect();
+        Response::new(address, data)
+    }
+
+    /// The data as 4 hex characters per word. Offsets in errors count from STX.
+    pub fn words(&self) -> Result<Vec<u16>, DecodeError> {
+        if !self.data.len().is_multiple_of(4) {
+            return Err(DecodeError::DataLength { expected: self.data.len().next_multiple_of(4), actual: self.data.len() });
+        }
+        hex_words(&self.data, 0, self.data.len() / 4).map_err(|e| shifted(e, 5))
+    }
+
+    /// The data as one `0` or `1` per bit. Offsets in errors count from STX.
+    pub fn bits(&self) -> Result<Vec<bool>, DecodeError> {
+        bit_values(&self.data, 0, self.data.len()).map_err(|e| shifted(e, 5))
+    }
+
+    /// Decodes the data as the answer to `command`, checking its length
+    /// against the requested number of points and its characters. A TT echo
+    /// is checked against its own number of characters only, comparing it
+    /// with what was sent is up to the caller. Offsets in errors count from STX.
+    pub fn decode(&self, command: &Command) -> Result<ResponseData, DecodeError> {
+        let expected = command.response_length().ok_or_else(|| DecodeError::NoResponseData(command.code().to_string()))?;
+        if self.data.len() != expected && !matches!(command, LoopbackTest(_)) {
+            return Err(DecodeError::DataLength { expected, actual: self.data.len() });
+        }
+        let data = match command {
+            ReadWords(_) | ExtendedReadWords(_) => hex_words(&self.data, 0, expected / 4).map(ResponseData::Words),
+            ReadBits(_) => bit_values(&self.data, 0, expected).map(ResponseData::Bits),
+            ReadPlcType => hex_u8(&self.data, 0, "PC type").map(|code| ResponseData::PlcType(PlcModel::from(code))),
+            _ => hex_u8(&self.data, 0, "number of characters").and_then(|length| {
+                let actual = self.data.len() - 2;
+                if length as usize != actual {
+                    return Err(DecodeError::DataLength { expected: length as usize, actual });
+                }
+                ascii(&self.data, 2, actual).map(ResponseData::Loopback)
+            }),
+        };
+        data.map_err(|e| shifted(e, 5))
+    }
+}
+
+/// The data of a [`Response`] decoded for the command it answers.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResponseData {
+    /// WR and QR.
+    Words(Vec<u16>),
+    /// BR.
+    Bits(Vec<bool>),
+    /// PC.
+    PlcType(PlcModel),
+    /// TT, the characters echoed by the station.
+    Loopback(String),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Message {
     Request(Request),
     Ack(Address),
@@ -116,52 +469,336 @@ pub enum Message {
     Response(Response),
 }
 
+impl Message {
+    /// Station and PLC number in the frame.
+    pub fn address(&self) -> Address {
+        match self {
+            Message::Request(r) => r.address,
+            Message::Ack(address) | Message::Nak(address) => *address,
+            Message::NakWithError(n) => n.address,
+            Message::Response(r) => r.address,
+        }
+    }
+}
+
 const STX: u8 = 2;
 const ETX: u8 = 3;
 const ACK: u8 = 6;
 
 const ENQ: u8 = 5;
 const NAK: u8 = 21; //0x15
-// const CR: u8 = 13; //0x0D \r
+const CR: u8 = 13; //0x0D \r
 const LF: u8 = 10; //0x0A \n
 
+/// Message format of the dedicated protocol, as configured in D8120.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum ProtocolFormat {
+    /// Frames end with ETX and the sum check or have a fixed length, there is
+    /// no terminator.
+    Format1,
+    /// Every frame is terminated with CR and LF.
+    #[default]
+    Format4,
+}
+
+/// Sum check setting of the computer link, as configured in D8120.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum SumCheck {
+    #[default]
+    Enabled,
+    Disabled,
+    /// Accept frames with and without sum check, encode with sum check.
+    /// Which one was received is reported by [`FxCodec::last_sum_check`].
+    /// In Format 1 a frame ending in ETX is only known to have no sum check
+    /// once the next frame starts or the input ends, see
+    /// [`FxCodec::decode_eof`](Decoder::decode_eof).
+    Tolerant,
+}
+
+
+fn is_start_byte(b: u8) -> bool {
+    matches!(b, STX | ENQ | ACK | NAK)
+}
 
 pub struct FxCodec {
+    pub(crate) format: ProtocolFormat,
+    pub(crate) sum_check: SumCheck,
+    last_sum_check: Option<bool>,
     next_index: usize,
     max_length: usize,
-    is_discarding: bool,
+    skipped_bytes: usize,
+}
+
+impl Default for FxCodec {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl FxCodec {
     pub fn new() -> FxCodec {
         FxCodec {
+            format: ProtocolFormat::Format4,
+            sum_check: SumCheck::Enabled,
+            last_sum_check: None,
             next_index: 0,
             max_length: usize::MAX,
-            is_discarding: false,
+            skipped_bytes: 0,
         }
     }
 
-    fn discard(&mut self, newline_offset: Option<usize>, read_to: usize, buf: &mut BytesMut) {
-        let discard_to = if let Some(offset) = newline_offset {
-            // If we found a newline, discard up to that offset and
-            // then stop discarding. On the next iteration, we'll try
-            // to read a line normally.
-            self.is_discarding = false;
-            offset + self.next_index + 1
-        } else {
-            // Otherwise, we didn't find a newline, so we'll discard
-            // everything we read. On the next iteration, we'll continue
-            // discarding up to max_len bytes unless we find a newline.
-            read_to
-        };
-        buf.advance(discard_to);
+    pub fn with_format(mut self, format: ProtocolFormat) -> FxCodec {
+        self.format = format;
+        self
+    }
+
+    pub fn format(&self) -> ProtocolFormat {
+        self.format
+    }
+
+    pub fn with_sum_check(mut self, sum_check: SumCheck) -> FxCodec {
+        self.sum_check = sum_check;
+        self
+    }
+
+    pub fn sum_check(&self) -> SumCheck {
+        self.sum_check
+    }
+
+    /// Whether the last decoded STX or ENQ frame carried a sum check, `None`
+    /// for ACK and NAK frames which never do.
+    pub fn last_sum_check(&self) -> Option<bool> {
+        self.last_sum_check
+    }
+
+    /// Total number of bytes thrown away while hunting for the start of a
+    /// frame: line noise, partial frames seen when joining mid-conversation
+    /// and frames interrupted by a new start character.
+    pub fn skipped_bytes(&self) -> usize {
+        self.skipped_bytes
+    }
+
+    /// Forgets how far the buffer was searched, for when its content was
+    /// thrown away.
+    pub(crate) fn reset(&mut self) {
         self.next_index = 0;
     }
+
+    fn skip(&mut self, count: usize, buf: &mut BytesMut) {
+        buf.advance(count);
+        self.skipped_bytes += count;
+        self.next_index = 0;
+    }
+
+    fn put_checksum(&self, dst: &mut BytesMut, start: usize) {
+        if self.sum_check != SumCheck::Disabled {
+            let checksum = checksum(&dst[start + 1..]);
+            dst.put(format!("{:02X}", checksum).as_bytes());
+        }
+    }
+
+    fn decoded(&mut self, frame: &Bytes) -> Result<Option<Message>, DecodeError> {
+        let result = decode_frame(frame, self.sum_check);
+        self.last_sum_check = result.as_ref().ok().and_then(|(_, sum_check)| *sum_check);
+        result.map(|(message, _)| Some(message))
+    }
+
+    fn put_terminator(&self, dst: &mut BytesMut) {
+        if self.format == ProtocolFormat::Format4 {
+            dst.put_u8(CR);
+            dst.put_u8(LF);
+        }
+    }
+
+    /// Format 4: frames end at the next LF.
+    fn decode_format4(&mut self, buf: &mut BytesMut) -> Result<Option<Message>, DecodeError> {
+        loop {
+            self.hunt_start(buf);
+            if buf.is_empty() {
+                return Ok(None);
+            }
+
+            // Determine how far into the buffer we'll search for a newline. If
+            // there's no max_length set, we'll read to the end of the buffer.
+            let read_to = cmp::min(self.max_length.saturating_add(1), buf.len());
+            let search_from = cmp::max(self.next_index, 1).min(read_to);
+
+            let end_offset = buf[search_from..read_to]
+                .iter()
+                .position(|b| *b == LF || is_start_byte(*b));
+
+            return if let Some(offset) = end_offset {
+                let end_index = offset + search_from;
+                if buf[end_index] != LF {
+                    // A new frame started before this one was terminated,
+                    // drop the incomplete one and decode the new one.
+                    self.skip(end_index, buf);
+                    continue;
+                }
+                // Found a line!
+                self.next_index = 0;
+                let line = buf.split_to(end_index + 1).freeze();
+                let end = without_carriage_return(&line[..line.len() - 1]).len();
+                self.decoded(&line.slice(..end))
+            } else if buf.len() > self.max_length {
+                // Reached the maximum length without finding a newline,
+                // drop what we have and hunt for the next start character.
+                self.skip(read_to, buf);
+                Err(DecodeError::LineTooLong)
+            } else {
+                // We didn't find a line or reach the length limit, so the next
+                // call will resume searching at the current offset.
+                self.next_index = read_to;
+                Ok(None)
+            };
+        }
+    }
+
+    /// Format 1: the length of a frame follows from its content.
+    fn decode_format1(&mut self, buf: &mut BytesMut, at_end: bool) -> Result<Option<Message>, DecodeError> {
+        loop {
+            self.hunt_start(buf);
+            if buf.is_empty() {
+                return Ok(None);
+            }
+
+            // A start character inside the frame means it was interrupted.
+            let interrupted_at = buf[1..].iter().position(|b| is_start_byte(*b)).map(|p| p + 1);
+            let available = interrupted_at.unwrap_or(buf.len());
+            let length = match frame_length(&buf[..available], at_end || interrupted_at.is_some(), self.sum_check) {
+                Ok(length) => length,
+                Err(e) => {
+                    self.skip(1, buf);
+                    return Err(e);
+                }
+            };
+            return match length {
+                Some(length) if length <= available => {
+                    let frame = buf.split_to(length).freeze();
+                    self.decoded(&frame)
+                }
+                _ if interrupted_at.is_some() => {
+                    self.skip(available, buf);
+                    continue;
+                }
+                _ if buf.len() > self.max_length => {
+                    let len = buf.len();
+                    self.skip(len, buf);
+                    Err(DecodeError::LineTooLong)
+                }
+                _ => Ok(None),
+            };
+        }
+    }
+
+    /// Discards everything in front of the first start character.
+    fn hunt_start(&mut self, buf: &mut BytesMut) {
+        match buf.iter().position(|b| is_start_byte(*b)) {
+            Some(0) => {},
+            Some(start) => self.skip(start, buf),
+            None => {
+                let len = buf.len();
+                self.skip(len, buf);
+            }
+        }
+    }
+}
+
+/// Length of the Format 1 frame at the start of `buf`, `None` if more bytes
+/// are needed to tell. `complete` is set when no more bytes will follow
+/// because a new frame starts right behind `buf`.
+fn frame_length(buf: &[u8], complete: bool, sum_check: SumCheck) -> Result<Option<usize>, DecodeError> {
+    let length = match buf[0] {
+        ACK => Some(5),
+        // NAK sent by the PLC carries an error code, the one sent by the
+        // computer does not.
+        NAK => match buf.get(5) {
+            Some(b) if b.is_ascii_hexdigit() => Some(7),
+            Some(_) => Some(5),
+            None if complete => Some(5),
+            None => None,
+        },
+        STX => buf
+            .iter()
+            .position(|b| *b == ETX)
+            .and_then(|etx| with_checksum(buf, etx + 1, complete, sum_check)),
+        ENQ => request_length(buf)?.and_then(|end| with_checksum(buf, end, complete, sum_check)),
+        _ => None,
+    };
+    Ok(length)
 }
 
-fn utf8(buf: &[u8]) -> Result<&str, io::Error> {
-    str::from_utf8(buf)
-        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Unable to decode input as UTF8"))
+/// Adds the sum check to a frame whose content ends at `end`.
+fn with_checksum(buf: &[u8], end: usize, complete: bool, sum_check: SumCheck) -> Option<usize> {
+    match sum_check {
+        SumCheck::Enabled => Some(end + 2),
+        SumCheck::Disabled => Some(end),
+        SumCheck::Tolerant => match buf.get(end..end + 2) {
+            Some(sum) if sum.iter().all(u8::is_ascii_hexdigit) => Some(end + 2),
+            Some(_) => Some(end),
+            None if complete || buf.get(end).is_some_and(|b| !b.is_ascii_hexdigit()) => Some(end),
+            None => None,
+        },
+    }
+}
+
+/// Length of an ENQ frame without sum check.
+fn request_length(frame: &[u8]) -> Result<Option<usize>, DecodeError> {
+    // ENQ, station, plc, command, message wait time
+    const HEADER: usize = 8;
+    let Some(command_code) = frame.get(5..7) else {
+        return Ok(None);
+    };
+    // head device and number of device points
+    let points = |frame: &[u8], width: usize| -> Result<Option<usize>, DecodeError> {
+        if frame.len() < HEADER + width + 2 {
+            return Ok(None);
+        }
+        Ok(Some(hex_u8(frame, HEADER + width, "number of device points")? as usize))
+    };
+    let body = match command_code {
+        b"RR" | b"RS" | b"PC" => 0,
+        b"GW" => 1,
+        b"WR" | b"BR" => 7,
+        b"QR" => 9,
+        b"WW" | b"QW" => {
+            let width = device_width(command_code);
+            match points(frame, width)? {
+                Some(points) => width + 2 + points * 4,
+                None => return Ok(None),
+            }
+        }
+        b"BW" => match points(frame, 5)? {
+            Some(points) => 7 + bit_points(points as u8) as usize,
+            None => return Ok(None),
+        },
+        b"TT" => {
+            if frame.len() < HEADER + 2 {
+                return Ok(None);
+            }
+            2 + hex_u8(frame, 8, "number of characters")? as usize
+        }
+        // number of points, then device and value per point
+        b"BT" | b"WT" | b"QT" => {
+            if frame.len() < HEADER + 2 {
+                return Ok(None);
+            }
+            let points = hex_u8(frame, 8, "number of device points")? as usize;
+            let value = if command_code == b"BT" { 1 } else { 4 };
+            2 + points * (device_width(command_code) + value)
+        }
+        _ => return Err(DecodeError::UnknownCommand(String::from_utf8_lossy(command_code).into_owned())),
+    };
+    Ok(Some(HEADER + body))
+}
+
+/// Width of the device field, 7 characters for the Q commands.
+fn device_width(command_code: &[u8]) -> usize {
+    if command_code.first() == Some(&b'Q') {
+        7
+    } else {
+        5
+    }
 }
 
 fn without_carriage_return(s: &[u8]) -> &[u8] {
@@ -172,282 +809,469 @@ fn without_carriage_return(s: &[u8]) -> &[u8] {
     }
 }
 
-impl Decoder for FxCodec {
-    type Item = Message;
-    type Error = io::Error;
+fn field(frame: &[u8], offset: usize, len: usize) -> Result<&[u8], DecodeError> {
+    frame.get(offset..offset + len).ok_or(DecodeError::Truncated {
+        needed: offset + len,
+        actual: frame.len(),
+    })
+}
 
-    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+fn hex_field(frame: &[u8], offset: usize, len: usize, name: &'static str) -> Result<u32, DecodeError> {
+    field(frame, offset, len)?
+        .iter()
+        .try_fold(0u32, |value, &b| (b as char).to_digit(16).map(|digit| value << 4 | digit))
+        .ok_or(DecodeError::BadHex { field: name, offset })
+}
 
-        loop {
-            // Determine how far into the buffer we'll search for a newline. If
-            // there's no max_length set, we'll read to the end of the buffer.
-            let read_to = cmp::min(self.max_length.saturating_add(1), buf.len());
+fn hex_u8(frame: &[u8], offset: usize, name: &'static str) -> Result<u8, DecodeError> {
+    hex_field(frame, offset, 2, name).map(|v| v as u8)
+}
 
-            let newline_offset = buf[self.next_index..read_to]
-                .iter()
-                .position(|b| *b == LF);
+fn ascii(frame: &[u8], offset: usize, len: usize) -> Result<String, DecodeError> {
+    let chars = field(frame, offset, len)?;
+    if let Some(position) = chars.iter().position(|b| !b.is_ascii()) {
+        return Err(DecodeError::NonAscii { offset: offset + position });
+    }
+    Ok(chars.iter().map(|&b| b as char).collect())
+}
 
-            if self.is_discarding {
-                self.discard(newline_offset, read_to, buf);
-            } else {
-                return if let Some(offset) = newline_offset {
-                    // Found a line!
-                    let newline_index = offset + self.next_index;
-                    self.next_index = 0;
-                    let line = buf.split_to(newline_index + 1);
-                    let first:u8 = line[0];
-                    let line = &line[1..line.len() - 1];
-                    let line = without_carriage_return(line);
-                    let checksum = if first == STX || first == ENQ {
-                        checksum(&line[..line.len()-2])
-                    }
-                    else {
-                        0
-                    };
-                    let line = utf8(&line)?;
-                    // println!("line: {}", line);
-                    match first {
-                        STX => {
-                            let station = u8::from_str_radix(&line[0..2], 16).unwrap(); //TODO: check if bytes are there and conversion succeeded
-                            let plc = u8::from_str_radix(&line[2..4], 16).unwrap();
-                            let data = line[4..line.len()-3].to_string();
-                            let etx = line[line.len()-3..line.len()-2].as_bytes()[0];
-                            if etx != ETX {
-                                return Err(io::Error::new(io::ErrorKind::Other, "ETX not found at expected position in Message"));
-                            }
-                            let checksum_in_message = u8::from_str_radix(&line[line.len()-2..], 16).unwrap();
-                            if checksum_in_message != checksum {
-                                return Err(io::Error::new(io::ErrorKind::Other, "Invalid checksum in Message"));
-                            }
-                            Ok(Some(Message::Response(
-                                Response {
-                                    address: Address {
-                                        station,
-                                        plc,
-                                    },
-                                    data
-                                }
-                            )))
-                        },
-
-                        ACK => {
-                            //TDOO: check lengts and create acc with or without error code
-                            if line.len() == 4 {
-                                let station = u8::from_str_radix(&line[0..2], 16).unwrap(); //TODO: check if bytes are there and conversion succeeded
-                                let plc = u8::from_str_radix(&line[2..4], 16).unwrap();
-                                Ok(Some(Message::Ack(
-                                    Address {
-                                        station,
-                                        plc
-                                    }
-                                )))
-                            }
-                            else {
-                                Err(io::Error::new(io::ErrorKind::Other, "Invalid ACK Message"))
-                            }
-                        },
-                        NAK => {
-                            match line.len() {
-                                4 => {
-                                    let station = u8::from_str_radix(&line[0..2], 16).unwrap();
-                                    let plc = u8::from_str_radix(&line[2..4], 16).unwrap();
-                                    Ok(Some(Message::Nak(
-                                        Address {
-                                            station,
-                                            plc
-                                        }
-                                    )))
-                                },
-                                6 => {
-                                    let station = u8::from_str_radix(&line[0..2], 16).unwrap();
-                                    let plc = u8::from_str_radix(&line[2..4], 16).unwrap();
-                                    let error_code = u8::from_str_radix(&line[4..6], 16).unwrap();
-                                    Ok(Some(Message::NakWithError(
-                                        NakWithError {
-                                            address: Address {
-                                                station,
-                                                plc
-                                            },
-                                            error_code
-                                        }
-                                    )))
-                                },
-                                _ => {
-                                    Err(io::Error::new(io::ErrorKind::Other, "Invalid NAK Message"))
-                                }
-                            }
-
-                        },
-
-                        ENQ => {
-                            if line.len() < 7 {
-                                Err(io::Error::new(io::ErrorKind::Other, "Not complete header in ENQ Message"))
-                            }
-                            else {
-                                let station = u8::from_str_radix(&line[0..2], 16).unwrap();
-                                let plc = u8::from_str_radix(&line[2..4], 16).unwrap();
-                                let command_code = &line[4..6];
-                                let command = match command_code {
-                                    "WW" => {
-                                        let head_device = line[7..12].to_string();
-                                        let number_of_device_points = u8::from_str_radix(&line[12..14], 16).unwrap();
-                                        let data = line[14..line.len()-2].to_string();
-                                        if data.len() != number_of_device_points as usize * 4 {
-                                            return Err(io::Error::new(io::ErrorKind::Other, format!("Command {} data length not correct.", command_code)))
-                                        }
-                                        WriteWords(
-                                            WriteWordsCommand {
-                                                head_device,
-                                                number_of_device_points,
-                                                data,
-                                            }
-                                        )
-                                    },
-                                    "WR" => {
-                                        let head_device = line[7..12].to_string();
-                                        let number_of_device_points = u8::from_str_radix(&line[12..14], 16).unwrap();
-                                        ReadWords(
-                                            ReadWordsCommand {
-                                                head_device,
-                                                number_of_device_points,
-                                            }
-                                        )
-                                    },
-                                    _ => {
-                                        return Err(io::Error::new(io::ErrorKind::Other, format!("Command {} not implemented", command_code)))
-                                    }
-                                };
-
-                                let msg_wait_time = u8::from_str_radix(&line[6..7], 16).unwrap();
-
-                                let checksum_in_message = u8::from_str_radix(&line[line.len()-2..], 16).unwrap();
-                                if checksum != checksum_in_message {
-                                    return Err(io::Error::new(io::ErrorKind::Other, "Invalid checksum in Message"))
-                                }
-                                Ok(Some(Message::Request(
-                                    Request {
-                                        address: Address {
-                                            station,
-                                            plc
-                                        },
-                                        command,
-                                        msg_wait_time,
-                                    }
-                                )))
-                            }
-                        }
+/// `count` words of 4 hex characters each, starting at `offset`.
+fn hex_words(frame: &[u8], offset: usize, count: usize) -> Result<Vec<u16>, DecodeError> {
+    (0..count)
+        .map(|i| hex_field(frame, offset + i * 4, 4, "data").map(|v| v as u16))
+        .collect()
+}
 
-                        _ => Err(io::Error::new(io::ErrorKind::Other, "Invalid Message")), //TODO: discard until find a start character?
-                    }
-                } else if buf.len() > self.max_length {
-                    // Reached the maximum length without finding a
-                    // newline, return an error and start discarding on the
-                    // next call.
-                    self.is_discarding = true;
-                    Err(io::Error::new(
-                        io::ErrorKind::Other,
-                        "line length limit exceeded",
-                    ))
-                } else {
-                    // We didn't find a line or reach the length limit, so the next
-                    // call will resume searching at the current offset.
-                    self.next_index = read_to;
-                    Ok(None)
-                };
+/// `count` bits of one `0` or `1` character each, starting at `offset`.
+fn bit_values(frame: &[u8], offset: usize, count: usize) -> Result<Vec<bool>, DecodeError> {
+    field(frame, offset, count)?
+        .iter()
+        .enumerate()
+        .map(|(i, b)| match b {
+            b'0' => Ok(false),
+            b'1' => Ok(true),
+            _ => Err(DecodeError::BadBit { offset: offset + i }),
+        })
+        .collect()
+}
+
+/// Moves the offset of an error found in a part of a frame by the position of that part.
+fn shifted(error: DecodeError, by: usize) -> DecodeError {
+    match error {
+        DecodeError::BadHex { field, offset } => DecodeError::BadHex { field, offset: offset + by },
+        DecodeError::BadBit { offset } => DecodeError::BadBit { offset: offset + by },
+        DecodeError::NonAscii { offset } => DecodeError::NonAscii { offset: offset + by },
+        error => error,
+    }
+}
+
+/// Bit commands send 256 points as `00`.
+fn bit_points(points: u8) -> u16 {
+    if points == 0 {
+        MAX_BIT_POINTS
+    } else {
+        points as u16
+    }
+}
+
+fn device(frame: &[u8], offset: usize, len: usize) -> Result<Device, DecodeError> {
+    ascii(frame, offset, len)?
+        .parse()
+        .map_err(|error| DecodeError::InvalidDevice { offset, error })
+}
+
+fn address(frame: &[u8]) -> Result<Address, DecodeError> {
+    Ok(Address {
+        station: hex_u8(frame, 1, "station")?,
+        plc: hex_u8(frame, 3, "plc")?,
+    })
+}
+
+/// Checks the two sum check characters at the end of `frame` against the sum
+/// of everything between the control character and the sum check.
+fn verify_checksum(frame: &[u8]) -> Result<(), DecodeError> {
+    if frame.len() < 3 {
+        return Err(DecodeError::Truncated { needed: 3, actual: frame.len() });
+    }
+    let sum_offset = frame.len() - 2;
+    let actual = hex_u8(frame, sum_offset, "checksum")?;
+    let expected = checksum(&frame[1..sum_offset]);
+    if expected != actual {
+        return Err(DecodeError::ChecksumMismatch { expected, actual });
+    }
+    Ok(())
+}
+
+/// Decodes a single frame, starting with its control character and without
+/// the CR/LF terminator. Also returns whether a sum check was present.
+/// Response data shares the memory of `frame`.
+fn decode_frame(frame: &Bytes, sum_check: SumCheck) -> Result<(Message, Option<bool>), DecodeError> {
+    let first = *frame.first().ok_or(DecodeError::Truncated { needed: 1, actual: 0 })?;
+    match first {
+        STX => {
+            // STX, station, plc, data, ETX, checksum
+            let has_checksum = match sum_check {
+                SumCheck::Enabled => true,
+                SumCheck::Disabled => false,
+                SumCheck::Tolerant => frame.last() != Some(&ETX),
+            };
+            let needed = if has_checksum { 8 } else { 6 };
+            if frame.len() < needed {
+                return Err(DecodeError::Truncated { needed, actual: frame.len() });
+            }
+            let address = address(frame)?;
+            let etx_offset = frame.len() - needed + 5;
+            if frame[etx_offset] != ETX {
+                return Err(DecodeError::MissingEtx);
+            }
+            if has_checksum {
+                verify_checksum(frame)?;
+            }
+            let data = frame.slice(5..etx_offset);
+            Ok((Message::Response(Response { address, data }), Some(has_checksum)))
+        }
+        ACK => {
+            if frame.len() != 5 {
+                return Err(DecodeError::InvalidLength { frame: "ACK", length: frame.len() });
+            }
+            Ok((Message::Ack(address(frame)?), None))
+        }
+        NAK => match frame.len() {
+            5 => Ok((Message::Nak(address(frame)?), None)),
+            7 => Ok((Message::NakWithError(NakWithError {
+                address: address(frame)?,
+                error_code: hex_u8(frame, 5, "error code")?,
+            }), None)),
+            length => Err(DecodeError::InvalidLength { frame: "NAK", length }),
+        },
+        ENQ => decode_request(frame, sum_check),
+        other => Err(DecodeError::UnknownControlByte(other)),
+    }
+}
+
+fn decode_request(frame: &[u8], sum_check: SumCheck) -> Result<(Message, Option<bool>), DecodeError> {
+    // ENQ, station, plc, command, message wait time, ..., checksum
+    if frame.len() < 8 {
+        return Err(DecodeError::Truncated { needed: 8, actual: frame.len() });
+    }
+    let address = address(frame)?;
+    let command_code = field(frame, 5, 2)?;
+    let msg_wait_time = hex_field(frame, 7, 1, "message wait time")? as u8;
+    let has_checksum = match sum_check {
+        SumCheck::Enabled => true,
+        SumCheck::Disabled => false,
+        SumCheck::Tolerant => request_length(frame)?.is_some_and(|end| frame.len() == end + 2),
+    };
+    let body_end = if has_checksum {
+        frame.len().checked_sub(2).ok_or(DecodeError::Truncated { needed: 10, actual: frame.len() })?
+    } else {
+        frame.len()
+    };
+    let command = match command_code {
+        b"WW" => WriteWords(decode_write_words(frame, body_end, 5)?),
+        b"QW" => ExtendedWriteWords(decode_write_words(frame, body_end, 7)?),
+        b"WR" => ReadWords(decode_read_words(frame, body_end, 5)?),
+        b"QR" => ExtendedReadWords(decode_read_words(frame, body_end, 7)?),
+        b"BW" => {
+            let head_device = device(frame, 8, 5)?;
+            let number_of_device_points = bit_points(hex_u8(frame, 13, "number of device points")?);
+            let expected = number_of_device_points as usize;
+            let actual = body_end.saturating_sub(15);
+            if actual != expected {
+                return Err(DecodeError::DataLength { expected, actual });
+            }
+            let data = bit_values(frame, 15, actual)?;
+            WriteBits(WriteBitsCommand {
+                head_device,
+                number_of_device_points,
+                data,
+            })
+        }
+        b"BR" => {
+            let head_device = device(frame, 8, 5)?;
+            let number_of_device_points = bit_points(hex_u8(frame, 13, "number of device points")?);
+            if body_end != 15 {
+                return Err(DecodeError::DataLength { expected: 0, actual: body_end.saturating_sub(15) });
+            }
+            ReadBits(ReadBitsCommand {
+                head_device,
+                number_of_device_points,
+            })
+        }
+        b"BT" => TestBits(TestBitsCommand {
+            points: decode_test_points(frame, body_end, 5, 1, |frame, offset| match frame[offset] {
+                b'0' => Ok(false),
+                b'1' => Ok(true),
+                _ => Err(DecodeError::BadBit { offset }),
+            })?,
+        }),
+        b"WT" => TestWords(decode_test_words(frame, body_end, 5)?),
+        b"QT" => ExtendedTestWords(decode_test_words(frame, body_end, 7)?),
+        b"GW" => {
+            if body_end != 9 {
+                return Err(DecodeError::DataLength { expected: 1, actual: body_end.saturating_sub(8) });
+            }
+            let set = match frame[8] {
+                b'0' => false,
+                b'1' => true,
+                _ => return Err(DecodeError::BadBit { offset: 8 }),
+            };
+            Global(GlobalCommand { set })
+        }
+        b"TT" => {
+            let expected = hex_u8(frame, 8, "number of characters")? as usize;
+            let actual = body_end.saturating_sub(10);
+            if actual != expected {
+                return Err(DecodeError::DataLength { expected, actual });
             }
+            LoopbackTest(LoopbackTestCommand {
+                data: ascii(frame, 10, actual)?,
+            })
         }
+        b"RR" | b"RS" | b"PC" => {
+            if body_end != 8 {
+                return Err(DecodeError::DataLength { expected: 0, actual: body_end.saturating_sub(8) });
+            }
+            match command_code {
+                b"RR" => RemoteRun,
+                b"RS" => RemoteStop,
+                _ => ReadPlcType,
+            }
+        }
+        _ => return Err(DecodeError::UnknownCommand(String::from_utf8_lossy(command_code).into_owned())),
+    };
+    if has_checksum {
+        verify_checksum(frame)?;
     }
+    Ok((Message::Request(Request {
+        address,
+        command,
+        msg_wait_time,
+    }), Some(has_checksum)))
+}
 
+/// Head device of `width` characters followed by the number of device points.
+fn decode_read_words(frame: &[u8], body_end: usize, width: usize) -> Result<ReadWordsCommand, DecodeError> {
+    let head_device = device(frame, 8, width)?;
+    let number_of_device_points = hex_u8(frame, 8 + width, "number of device points")?;
+    let data_offset = 10 + width;
+    if body_end != data_offset {
+        return Err(DecodeError::DataLength { expected: 0, actual: body_end.saturating_sub(data_offset) });
+    }
+    Ok(ReadWordsCommand {
+        head_device,
+        number_of_device_points,
+    })
+}
 
+fn decode_write_words(frame: &[u8], body_end: usize, width: usize) -> Result<WriteWordsCommand, DecodeError> {
+    let head_device = device(frame, 8, width)?;
+    let number_of_device_points = hex_u8(frame, 8 + width, "number of device points")?;
+    let data_offset = 10 + width;
+    let expected = number_of_device_points as usize * 4;
+    let actual = body_end.saturating_sub(data_offset);
+    if actual != expected {
+        return Err(DecodeError::DataLength { expected, actual });
+    }
+    let data = hex_words(frame, data_offset, number_of_device_points as usize)?;
+    Ok(WriteWordsCommand {
+        head_device,
+        number_of_device_points,
+        data,
+    })
 }
 
-impl Encoder<Message> for FxCodec {
-    // type Item = Message;
-    type Error = io::Error;
+fn decode_test_words(frame: &[u8], body_end: usize, width: usize) -> Result<TestWordsCommand, DecodeError> {
+    Ok(TestWordsCommand {
+        points: decode_test_points(frame, body_end, width, 4, |frame, offset| {
+            hex_field(frame, offset, 4, "data").map(|v| v as u16)
+        })?,
+    })
+}
 
-    fn encode(&mut self, item: Message, dst: &mut BytesMut) -> Result<(), Self::Error> {
+/// Decodes the device and value pairs of BT, WT and QT, with devices `width`
+/// and values `value_width` characters long.
+fn decode_test_points<T>(
+    frame: &[u8],
+    body_end: usize,
+    width: usize,
+    value_width: usize,
+    value: impl Fn(&[u8], usize) -> Result<T, DecodeError>,
+) -> Result<Vec<(Device, T)>, DecodeError> {
+    let size = width + value_width;
+    let points = hex_u8(frame, 8, "number of device points")? as usize;
+    let expected = points * size;
+    let actual = body_end.saturating_sub(10);
+    if actual != expected {
+        return Err(DecodeError::DataLength { expected, actual });
+    }
+    (0..points)
+        .map(|i| {
+            let offset = 10 + i * size;
+            Ok((device(frame, offset, width)?, value(frame, offset + width)?))
+        })
+        .collect()
+}
+
+impl Decoder for FxCodec {
+    type Item = Message;
+    type Error = DecodeError;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        match self.format {
+            ProtocolFormat::Format1 => self.decode_format1(buf, false),
+            ProtocolFormat::Format4 => self.decode_format4(buf),
+        }
+    }
+
+    /// Nothing follows the buffered bytes, a Format 1 frame ending in ETX
+    /// is taken as one without sum check.
+    fn decode_eof(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        match self.format {
+            ProtocolFormat::Format1 => self.decode_format1(buf, true),
+            ProtocolFormat::Format4 => self.decode_format4(buf),
+        }
+    }
+}
 
+impl FxCodec {
+    fn encode_message(&self, item: Message, dst: &mut BytesMut, start: usize) -> Result<(), io::Error> {
         match item {
             Message::Response(p) =>  {
-                dst.reserve(9 + p.data.len());
+                dst.reserve(10 + p.data.len());
                 dst.put_u8(STX); //STX
                 dst.put(format!("{:02X}", p.address.station).as_bytes());
                 dst.put(format!("{:02X}", p.address.plc).as_bytes());
-                dst.put(p.data.as_bytes());
+                if let Some(b) = p.data.iter().find(|b| !b.is_ascii() || b.is_ascii_control()) {
+                    return Err(invalid_input(format!("response data contains 0x{:02X}", b)));
+                }
+                dst.put(&p.data[..]);
                 dst.put_u8(ETX);
-                let checksum = checksum(&dst[1..]);
-                dst.put(format!("{:02X}", checksum).as_bytes());
-                dst.put_u8(LF);
+                self.put_checksum(dst, start);
+                self.put_terminator(dst);
                 Ok(())
             },
             Message::Ack(p) => {
-                dst.reserve(6);
+                dst.reserve(7);
                 dst.put_u8(ACK); //ACK
                 dst.put(format!("{:02X}", p.station).as_bytes());
                 dst.put(format!("{:02X}", p.plc).as_bytes());
-                dst.put_u8(LF);
+                self.put_terminator(dst);
                 Ok(())
             },
             Message::Nak(p) => {
-                dst.reserve(6);
+                dst.reserve(7);
                 dst.put_u8(NAK); //ACK
                 dst.put(format!("{:02X}", p.station).as_bytes());
                 dst.put(format!("{:02X}", p.plc).as_bytes());
-                dst.put_u8(LF);
+                self.put_terminator(dst);
                 Ok(())
             },
             Message::NakWithError(p) => {
-                dst.reserve(8);
+                dst.reserve(9);
                 dst.put_u8(NAK);
                 dst.put(format!("{:02X}", p.address.station).as_bytes());
                 dst.put(format!("{:02X}", p.address.plc).as_bytes());
                 dst.put(format!("{:02X}", p.error_code).as_bytes());
-                dst.put_u8(LF);
+                self.put_terminator(dst);
                 Ok(())
             },
             Message::Request(p) => {
                 let command_size = match &p.command {
-                    WriteWords(c) => {
-                        //TODO: could check if data.len is correct with number_of_device_points
-                        4 + c.data.len()
-                    }
-                    ReadWords(_) => {
-                        4
-                    }
+                    WriteWords(c) => 7 + c.data.len() * 4,
+                    ExtendedWriteWords(c) => 9 + c.data.len() * 4,
+                    WriteBits(c) => 7 + c.data.len(),
+                    ReadWords(_) | ReadBits(_) => 7,
+                    ExtendedReadWords(_) => 9,
+                    TestBits(c) => 2 + c.points.len() * 6,
+                    TestWords(c) => 2 + c.points.len() * 9,
+                    ExtendedTestWords(c) => 2 + c.points.len() * 11,
+                    RemoteRun | RemoteStop | ReadPlcType => 0,
+                    Global(_) => 1,
+                    LoopbackTest(c) => 2 + c.data.len(),
+                    Raw(c) => c.body.len(),
                 };
-                dst.reserve(9 + command_size); //TODO: take in to account the length of data
-                dst.put_u8(ENQ); //ACK
+                dst.reserve(12 + command_size);
+                dst.put_u8(ENQ);
 
                 dst.put(format!("{:02X}", p.address.station).as_bytes());
                 dst.put(format!("{:02X}", p.address.plc).as_bytes());
+                if let Raw(c) = &p.command {
+                    if c.code.len() != 2 || !c.code.bytes().all(|b| b.is_ascii_uppercase() || b.is_ascii_digit()) {
+                        return Err(invalid_input(format!("command code {:?} is not two letters or digits", c.code)));
+                    }
+                }
+                dst.put(p.command.code().as_bytes());
+                dst.put(&format!("{:02X}", p.msg_wait_time).as_bytes()[1..2]);
 
                 match &p.command {
-                    WriteWords(_) => {
-                        dst.put("WW".as_bytes());
-                    },
-                    ReadWords(_) => {
-                        dst.put("WR".as_bytes());
-                    },
-                };
-
-                dst.put(format!("{:02X}", p.msg_wait_time)[1..2].as_bytes());
-
-                match &p.command {
-                    WriteWords(c) => {
-                        dst.put(format!("{}", c.head_device).as_bytes());  //TODO: make sure its 5 and valid
-                        dst.put(format!("{:02X}", c.number_of_device_points).as_bytes());
-                        dst.put(format!("{}", c.data).as_bytes()); //TODO: make sure its valid
+                    WriteWords(c) | ExtendedWriteWords(c) => {
+                        if c.data.len() != c.number_of_device_points as usize {
+                            return Err(invalid_input(format!("{} data holds {} words instead of {}", p.command.code(), c.data.len(), c.number_of_device_points)));
+                        }
+                        dst.put(command_device_code(&p.command, &c.head_device)?.as_bytes());
+                        dst.put(word_points_code(c.number_of_device_points)?.as_bytes());
+                        for word in &c.data {
+                            dst.put(format!("{:04X}", word).as_bytes());
+                        }
                     },
-                    ReadWords(c) => {
-                        dst.put(format!("{}", c.head_device).as_bytes());  //TODO: make sure its 5 and valid
-                        dst.put(format!("{:02X}", c.number_of_device_points).as_bytes());
+                    ReadWords(c) | ExtendedReadWords(c) => {
+                        dst.put(command_device_code(&p.command, &c.head_device)?.as_bytes());
+                        dst.put(word_points_code(c.number_of_device_points)?.as_bytes());
+                    }
+                    WriteBits(c) => {
+                        if c.data.len() != c.number_of_device_points as usize {
+                            return Err(invalid_input(format!("BW data holds {} bits instead of {}", c.data.len(), c.number_of_device_points)));
+                        }
+                        dst.put(bit_device_code(&p.command, &c.head_device)?.as_bytes());
+                        dst.put(bit_points_code(c.number_of_device_points)?.as_bytes());
+                        for bit in &c.data {
+                            dst.put_u8(if *bit { b'1' } else { b'0' });
+                        }
+                    }
+                    ReadBits(c) => {
+                        dst.put(bit_device_code(&p.command, &c.head_device)?.as_bytes());
+                        dst.put(bit_points_code(c.number_of_device_points)?.as_bytes());
+                    }
+                    TestBits(c) => {
+                        dst.put(test_points_code(c.points.len(), MAX_TEST_BITS)?.as_bytes());
+                        for (device, value) in &c.points {
+                            dst.put(bit_device_code(&p.command, device)?.as_bytes());
+                            dst.put_u8(if *value { b'1' } else { b'0' });
+                        }
+                    }
+                    TestWords(c) | ExtendedTestWords(c) => {
+                        dst.put(test_points_code(c.points.len(), MAX_TEST_WORDS)?.as_bytes());
+                        for (device, value) in &c.points {
+                            dst.put(command_device_code(&p.command, device)?.as_bytes());
+                            dst.put(format!("{:04X}", value).as_bytes());
+                        }
+                    }
+                    RemoteRun | RemoteStop | ReadPlcType => {}
+                    Global(c) => dst.put_u8(if c.set { b'1' } else { b'0' }),
+                    LoopbackTest(c) => {
+                        let data = c.data.as_bytes();
+                        if data.is_empty() || data.len() > MAX_LOOPBACK_CHARACTERS {
+                            return Err(invalid_input(format!("{} characters, 1 to {} allowed", data.len(), MAX_LOOPBACK_CHARACTERS)));
+                        }
+                        // control characters would break the framing
+                        if !data.iter().all(|b| *b == b' ' || b.is_ascii_graphic()) {
+                            return Err(invalid_input(format!("TT data {:?} is not printable ASCII", c.data)));
+                        }
+                        dst.put(format!("{:02X}", data.len()).as_bytes());
+                        dst.put(data);
+                    }
+                    Raw(c) => {
+                        if !c.body.bytes().all(|b| b == b' ' || b.is_ascii_graphic()) {
+                            return Err(invalid_input(format!("{} body {:?} is not printable ASCII", c.code, c.body)));
+                        }
+                        dst.put(c.body.as_bytes());
                     }
                 };
 
-                let checksum = checksum(&dst[1..]);
-                dst.put(format!("{:02X}", checksum).as_bytes());
+                self.put_checksum(dst, start);
 
-                dst.put_u8(LF);
+                self.put_terminator(dst);
                 Ok(())
             },
         }
@@ -455,174 +1279,95 @@ impl Encoder<Message> for FxCodec {
     }
 }
 
-fn checksum(data: &[u8]) -> u8 {
-    let mut checksum = 0u8;
-    for byte in data.iter() {
-        checksum = checksum.wrapping_add(*byte);
-    }
-    checksum
-}
+impl Encoder<Message> for FxCodec {
+    // type Item = Message;
+    type Error = io::Error;
 
-pub struct Client {
-    pub address: Address,
-    pub msg_wait_time: u8,
-    reader: FramedRead<ReadHalf<SerialStream>,FxCodec>,
-    writer: FramedWrite<WriteHalf<SerialStream>,FxCodec>,
-}
-impl Client {
-    pub fn new(station: u8, plc: u8, transport: SerialStream) -> Self {
-        let (rx_port, tx_port) = tokio::io::split(transport);
-        let reader = tokio_util::codec::FramedRead::new(rx_port, FxCodec::new());
-        let writer = tokio_util::codec::FramedWrite::new(tx_port, FxCodec::new());
-        Client {
-            address: Address {
-                station,
-                plc,
-            },
-            msg_wait_time: 0,
-            reader,
-            writer,
-        }
-    }
-
-    pub async fn write_i16(&mut self, head_devide: String, value: i16) { //TODO: return the errors
-        let data = format!("{:04X}", value);
-        self.writer.send(Message::Request(Request::new(self.address, self.msg_wait_time, Command::WriteWords(WriteWordsCommand::new(head_devide, 1, data))))).await.expect("write enq");
-        if let Some(message_result) = self.reader.next().await { //TODO: handle the timeout
-            match message_result {
-                Ok(message) => {
-                    match message {
-                        Message::Ack(_r) => {
-                            //println!("Response Ack: {:?}", r);
-                        },
-                        _ => {
-                            println!("Unexpected Response Message: {:?}", &message);
-                        }
-                    }
-                },
-                Err(error) => {
-                    println!("error: {:?}", error);
-                }
-            }
+    fn encode(&mut self, item: Message, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let start = dst.len();
+        let result = self.encode_message(item, dst, start);
+        if result.is_err() {
+            // don't leave half a frame behind to be sent with the next one
+            dst.truncate(start);
         }
+        result
     }
+}
 
-    pub async fn write_i32(&mut self, head_devide: String, value: i32) { //TODO: return the errors
-        let data = format!("{:08X}", value);
-        //println!("write_i32 send request");
-        self.writer.send(Message::Request(Request::new(self.address, self.msg_wait_time, Command::WriteWords(WriteWordsCommand::new(head_devide, 2, data))))).await.expect("write enq");
-        self.writer.flush().await.expect("write enq");
-        //println!("write_i32 wait result");
-        if let Some(message_result) = self.reader.next().await { //TODO: handle the timeout
-            match message_result {
-                Ok(message) => {
-                    match message {
-                        Message::Ack(_r) => {
-                            //println!("Response Ack: {:?}", r);
-                        },
-                        _ => {
-                            println!("Unexpected Response Message: {:?}", &message);
-                        }
-                    }
-                },
-                Err(error) => {
-                    println!("error: {:?}", error);
-                }
-            }
-        }
-    }
+pub(crate) fn invalid_input<E>(error: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidInput, error)
+}
 
-    pub async fn read_i32(&mut self, head_devide: String) -> Result<i32, io::Error> { //TODO: return the errors
-        //println!("read_i32 send request");
-        self.writer.send( Message::Request(Request::new(self.address, self.msg_wait_time, Command::ReadWords(ReadWordsCommand::new(head_devide,2))))).await?;//.expect("write enq");
-        self.writer.flush().await?;
-        //println!("read_i32 wait result");
-        if let Some(message_result) = self.reader.next().await {
-            match message_result {
-                Ok(message) => {
-
-                    match message {
-                        Message::Response(r) => {
-                            //println!("Response Message: {:?}", r);
-                            let v= u32::from_str_radix(&r.data, 16).unwrap();
-                            self.writer.send(Message::Ack(self.address)).await?;
-                            self.writer.flush().await?;
-                            return Ok(v as i32);
-                        },
-                        _ => {
-                            println!("Unexpected Response Message: {:?}", &message);
-                            self.writer.send(Message::Nak(self.address)).await?;
-                            self.writer.flush().await?;
-                            return Err(io::Error::new(io::ErrorKind::Other, format!("No Ack Received on Read but got: {:?}", &message)));
+fn device_code(device: &Device) -> Result<String, io::Error> {
+    device.code().map_err(invalid_input)
+}
 
-                        }
-                    }
+/// The device field of the width used by `command`.
+fn command_device_code(command: &Command, device: &Device) -> Result<String, io::Error> {
+    match command {
+        ExtendedReadWords(_) | ExtendedWriteWords(_) | ExtendedTestWords(_) => device.extended_code().map_err(invalid_input),
+        _ => device_code(device),
+    }
+}
 
-                },
-                Err(error) => {
-                    println!("error: {:?}", error);
-                    return Err(error);
-                }
-            }
-        }
-        return Err(io::Error::new(io::ErrorKind::Other, format!("No Response Recevied")));
-    }
-    pub async fn read_i16(&mut self, head_devide: String) -> Result<i16, io::Error> { //TODO: return the errors
-        //println!("read_i32 send request");
-        self.writer.send( Message::Request(Request::new(self.address, self.msg_wait_time, Command::ReadWords(ReadWordsCommand::new(head_devide,1))))).await?;//.expect("write enq");
-        self.writer.flush().await?;
-        //println!("read_i32 wait result");
-        if let Some(message_result) = self.reader.next().await {
-            match message_result {
-                Ok(message) => {
-
-                    match message {
-                        Message::Response(r) => {
-                            println!("Response Message: {:?}", r);
-                            let v= u16::from_str_radix(&r.data, 16).unwrap();
-                            self.writer.send(Message::Ack(self.address)).await?;
-                            self.writer.flush().await?;
-                            return Ok(v as i16);
-                        },
-                        _ => {
-                            println!("Unexpected Response Message: {:?}", &message);
-                            self.writer.send(Message::Nak(self.address)).await?;
-                            self.writer.flush().await?;
-                            return Err(io::Error::new(io::ErrorKind::Other, format!("No Ack Received on Read but got: {:?}", &message)));
+/// The device field of a bit command, which only addresses X, Y, M, S and timer or counter contacts.
+fn bit_device_code(command: &Command, device: &Device) -> Result<String, io::Error> {
+    if !device.kind.is_bit() {
+        return Err(invalid_input(format!("{} is a word device, {} takes bit devices only", device, command.code())));
+    }
+    device_code(device)
+}
 
-                        }
-                    }
+fn word_points_code(points: u8) -> Result<String, io::Error> {
+    if points == 0 || points > MAX_WORD_POINTS {
+        return Err(invalid_input(format!("{} word points, 1 to {} allowed", points, MAX_WORD_POINTS)));
+    }
+    Ok(format!("{:02X}", points))
+}
 
-                },
-                Err(error) => {
-                    println!("error: {:?}", error);
-                    return Err(error);
-                }
-            }
-        }
-        return Err(io::Error::new(io::ErrorKind::Other, format!("No Response Recevied")));
+fn bit_points_code(points: u16) -> Result<String, io::Error> {
+    if points == 0 || points > MAX_BIT_POINTS {
+        return Err(invalid_input(format!("{} bit points, 1 to {} allowed", points, MAX_BIT_POINTS)));
     }
+    Ok(format!("{:02X}", points % MAX_BIT_POINTS))
+}
 
+fn test_points_code(points: usize, max: usize) -> Result<String, io::Error> {
+    if points == 0 || points > max {
+        return Err(invalid_input(format!("{} points, 1 to {} allowed", points, max)));
+    }
+    Ok(format!("{:02X}", points))
 }
 
+fn checksum(data: &[u8]) -> u8 {
+    let mut checksum = 0u8;
+    for byte in data.iter() {
+        checksum = checksum.wrapping_add(*byte);
+    }
+    checksum
+}
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
+    #[allow(non_snake_case)]
     fn decode_WriteWordsCommand() {
         let mut codec = FxCodec::new();
         let mut buf = BytesMut::with_capacity(1000);
 
         let address = Address::new(0, 255);
-        let message = Message::Request(Request::new(address,0, Command::WriteWords(WriteWordsCommand::new("M0640".to_string(), 2, "2347AB96".to_string()))));
+        let message = Message::Request(Request::new(address,0, Command::WriteWords(WriteWordsCommand::new("M0640".parse().unwrap(), vec![0x2347, 0xAB96]))));
 
-        codec.encode(message, &mut buf);
+        codec.encode(message, &mut buf).unwrap();
         let b = buf.split();
         let restult = b.as_ref();
 
-        assert_eq!(restult, b"\x0500FFWW0M0640022347AB9605\n");
+        assert_eq!(restult, b"\x0500FFWW0M0640022347AB9605\r\n");
     }
 
     #[test]
@@ -643,5 +1388,438 @@ mod tests {
         assert_eq!(result, v);
     }
 
+    fn decode_one(input: &[u8]) -> Result<Option<Message>, DecodeError> {
+        let mut codec = FxCodec::new();
+        let mut buf = BytesMut::from(input);
+        codec.decode(&mut buf)
+    }
+
+    #[test]
+    fn decode_errors() {
+        assert!(matches!(decode_one(b"\x0200FF\n"), Err(DecodeError::Truncated { needed: 8, .. })));
+        assert!(matches!(decode_one(b"\x060GFF\n"), Err(DecodeError::BadHex { field: "station", offset: 1 })));
+        assert!(matches!(decode_one(b"\x0200FF1234X00\n"), Err(DecodeError::MissingEtx)));
+        assert!(matches!(decode_one(b"\x0200FF1234\x0300\n"), Err(DecodeError::ChecksumMismatch { expected: 0xB9, actual: 0x00 })));
+        assert!(matches!(decode_frame(&Bytes::from_static(b"\x0700FF"), SumCheck::Enabled), Err(DecodeError::UnknownControlByte(0x07))));
+        assert!(matches!(decode_one(b"\x0500FFXX0D010601\n"), Err(DecodeError::UnknownCommand(c)) if c == "XX"));
+        assert!(matches!(decode_one(b"\x0500FFWW0D010602\n"), Err(DecodeError::DataLength { expected: 8, actual: 0 })));
+        assert!(matches!(decode_one(b"\x1500FF0\n"), Err(DecodeError::InvalidLength { frame: "NAK", length: 6 })));
+        assert!(matches!(decode_one(b"\x0500FFWR0X00180200\n"), Err(DecodeError::InvalidDevice { offset: 8, .. })));
+    }
+
+    #[test]
+    fn decode_response() {
+        let message = decode_one(b"\x0200FF1234\x03B9\r\n").unwrap().unwrap();
+        match message {
+            Message::Response(r) => {
+                assert_eq!(r.address.plc, 0xFF);
+                assert_eq!(r.data, "1234");
+            }
+            other => panic!("unexpected message {:?}", other),
+        }
+    }
+
+    #[test]
+    fn decode_skips_leading_garbage() {
+        let mut codec = FxCodec::new();
+        // tail of a response sent before we joined the line, then an ACK
+        let mut buf = BytesMut::from(&b"34\x03B9\r\n\x0605FF\r\n"[..]);
+        assert!(matches!(codec.decode(&mut buf), Ok(Some(Message::Ack(Address { station: 5, plc: 0xFF })))));
+        assert_eq!(codec.skipped_bytes(), 7);
+        assert!(buf.is_empty());
+
+        let mut buf = BytesMut::from(&b"\xFF\x00noise"[..]);
+        assert!(matches!(codec.decode(&mut buf), Ok(None)));
+        assert_eq!(codec.skipped_bytes(), 14);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn decode_recovers_from_interrupted_frame() {
+        let mut codec = FxCodec::new();
+        let mut buf = BytesMut::from(&b"\x0200FF12"[..]);
+        assert!(matches!(codec.decode(&mut buf), Ok(None)));
+        buf.put(&b"\x0200FF1234\x03B9\r\n"[..]);
+        match codec.decode(&mut buf) {
+            Ok(Some(Message::Response(r))) => assert_eq!(r.data, "1234"),
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert_eq!(codec.skipped_bytes(), 7);
+    }
+
+    #[test]
+    fn encode_rejects_device_outside_field() {
+        let mut codec = FxCodec::new();
+        let mut buf = BytesMut::new();
+        let request = Request::new(Address::new(0, 0xFF), 0, Command::ReadWords(ReadWordsCommand::new("R10000".parse().unwrap(), 1)));
+        assert_eq!(codec.encode(Message::Request(request), &mut buf).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn nak_error_codes() {
+        let nak = NakWithError::new(Address::new(5, 0xFF), 0x02);
+        assert_eq!(nak.error_code(), NakErrorCode::SumCheck);
+        assert_eq!(nak.error_code().to_string(), "sum check error");
+        assert_eq!(nak.address().station, 5);
+        assert_eq!(NakErrorCode::from(0x42), NakErrorCode::Unknown(0x42));
+        for code in 0..=u8::MAX {
+            assert_eq!(NakErrorCode::from(code).code(), code);
+        }
+    }
+
+    #[test]
+    fn extended_word_commands() {
+        let mut codec = FxCodec::new();
+        let mut buf = BytesMut::new();
+        let address = Address::new(0, 0xFF);
+        let r10000: Device = "R10000".parse().unwrap();
+        let command = Command::read_words(r10000, 2);
+        assert!(matches!(command, Command::ExtendedReadWords(_)));
+        assert!(matches!(Command::read_words("D106".parse().unwrap(), 2), Command::ReadWords(_)));
+        codec.encode(Message::Request(Request::new(address, 0, command)), &mut buf).unwrap();
+        assert_eq!(&buf[..], b"\x0500FFQR0R0100000294\r\n");
+        match codec.decode(&mut buf) {
+            Ok(Some(Message::Request(Request { command: Command::ExtendedReadWords(c), .. }))) => {
+                assert_eq!(c.head_device, r10000);
+                assert_eq!(c.number_of_device_points, 2);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let command = Command::write_words(r10000, vec![0x1234]);
+        codec.encode(Message::Request(Request::new(address, 0, command)), &mut buf).unwrap();
+        assert!(buf.starts_with(b"\x0500FFQW0R010000011234"));
+        match codec.decode(&mut buf) {
+            Ok(Some(Message::Request(Request { command: Command::ExtendedWriteWords(c), .. }))) => assert_eq!(c.data, [0x1234]),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let points = vec![(r10000, 0xABCD), ("D8000".parse().unwrap(), 1)];
+        let command = Command::ExtendedTestWords(TestWordsCommand::new(points.clone()));
+        codec.encode(Message::Request(Request::new(address, 0, command)), &mut buf).unwrap();
+        assert!(buf.starts_with(b"\x0500FFQT002R010000ABCDD0080000001"));
+        match codec.decode(&mut buf) {
+            Ok(Some(Message::Request(Request { command: Command::ExtendedTestWords(c), .. }))) => assert_eq!(c.points, points),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn encode_format1_without_terminator() {
+        let mut codec = FxCodec::new().with_format(ProtocolFormat::Format1);
+        let mut buf = BytesMut::new();
+        codec.encode(Message::Ack(Address::new(5, 0xFF)), &mut buf).unwrap();
+        let request = Request::new(Address::new(0, 0xFF), 0, Command::ReadWords(ReadWordsCommand::new("D0106".parse().unwrap(), 2)));
+        codec.encode(Message::Request(request), &mut buf).unwrap();
+        assert_eq!(&buf[..], b"\x0605FF\x0500FFWR0D01060232");
+    }
+
+    #[test]
+    fn decode_format1_by_length() {
+        let mut codec = FxCodec::new().with_format(ProtocolFormat::Format1);
+        let mut buf = BytesMut::from(&b"\x0500FFWW0M0640022347AB96"[..]);
+        assert!(matches!(codec.decode(&mut buf), Ok(None)));
+        buf.put(&b"05\x0500FFWR0D0106"[..]);
+        assert!(matches!(codec.decode(&mut buf), Ok(Some(Message::Request(Request { command: Command::WriteWords(_), .. })))));
+        assert!(matches!(codec.decode(&mut buf), Ok(None)));
+        buf.put(&b"0232\x0200FF1234\x03B9\x1500FF02\x1500FF"[..]);
+        assert!(matches!(codec.decode(&mut buf), Ok(Some(Message::Request(Request { command: Command::ReadWords(_), .. })))));
+        assert!(matches!(codec.decode(&mut buf), Ok(Some(Message::Response(_)))));
+        assert!(matches!(codec.decode(&mut buf), Ok(Some(Message::NakWithError(_)))));
+        // a NAK without error code is only known to be complete once the next frame starts
+        assert!(matches!(codec.decode(&mut buf), Ok(None)));
+        buf.put(&b"\x0600FF"[..]);
+        assert!(matches!(codec.decode(&mut buf), Ok(Some(Message::Nak(_)))));
+        assert!(matches!(codec.decode(&mut buf), Ok(Some(Message::Ack(_)))));
+        assert_eq!(codec.skipped_bytes(), 0);
+    }
+
+    #[test]
+    fn encode_without_sum_check() {
+        let mut codec = FxCodec::new().with_sum_check(SumCheck::Disabled);
+        let mut buf = BytesMut::new();
+        let request = Request::new(Address::new(0, 0xFF), 0, Command::ReadWords(ReadWordsCommand::new("D0106".parse().unwrap(), 2)));
+        codec.encode(Message::Request(request), &mut buf).unwrap();
+        codec.encode(Message::Response(Response::new(Address::new(0, 0xFF), "1234".to_string())), &mut buf).unwrap();
+        assert_eq!(&buf[..], b"\x0500FFWR0D010602\r\n\x0200FF1234\x03\r\n");
+    }
+
+    #[test]
+    fn decode_without_sum_check() {
+        for format in [ProtocolFormat::Format1, ProtocolFormat::Format4] {
+            let mut codec = FxCodec::new().with_format(format).with_sum_check(SumCheck::Disabled);
+            let mut buf = BytesMut::new();
+            let request = Request::new(Address::new(0, 0xFF), 0, Command::ReadWords(ReadWordsCommand::new("D0106".parse().unwrap(), 2)));
+            codec.encode(Message::Request(request), &mut buf).unwrap();
+            codec.encode(Message::Response(Response::new(Address::new(0, 0xFF), "1234".to_string())), &mut buf).unwrap();
+            assert!(matches!(codec.decode(&mut buf), Ok(Some(Message::Request(_)))));
+            assert_eq!(codec.last_sum_check(), Some(false));
+            match codec.decode(&mut buf) {
+                Ok(Some(Message::Response(r))) => assert_eq!(r.data, "1234"),
+                other => panic!("unexpected result {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn decode_tolerant_sum_check() {
+        let mut codec = FxCodec::new().with_sum_check(SumCheck::Tolerant);
+        let mut buf = BytesMut::from(&b"\x0500FFWR0D010602\r\n\x0500FFWR0D01060232\r\n\x0200FF1234\x03\r\n\x0200FF1234\x03B9\r\n"[..]);
+        for expected in [false, true, false, true] {
+            assert!(matches!(codec.decode(&mut buf), Ok(Some(_))));
+            assert_eq!(codec.last_sum_check(), Some(expected));
+        }
+
+        let mut codec = FxCodec::new().with_format(ProtocolFormat::Format1).with_sum_check(SumCheck::Tolerant);
+        let mut buf = BytesMut::from(&b"\x0200FF1234\x03B9\x0200FF1234\x03\x0600FF"[..]);
+        for expected in [Some(true), Some(false), None] {
+            assert!(matches!(codec.decode(&mut buf), Ok(Some(_))));
+            assert_eq!(codec.last_sum_check(), expected);
+        }
+
+        // the last frame on the line, a sum check may still follow until the input ends
+        buf.put(&b"\x0200FF1234\x03"[..]);
+        assert!(matches!(codec.decode(&mut buf), Ok(None)));
+        assert!(matches!(codec.decode_eof(&mut buf), Ok(Some(Message::Response(_)))));
+        assert_eq!(codec.last_sum_check(), Some(false));
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn raw_commands_and_response_data() {
+        let mut codec = FxCodec::new();
+        let mut buf = BytesMut::new();
+        let address = Address::new(0, 0xFF);
+        let raw = Command::Raw(RawCommand::new("WR".to_string(), "D010002".to_string()));
+        codec.encode(Message::Request(Request::new(address, 0, raw)), &mut buf).unwrap();
+        let typed = Command::ReadWords(ReadWordsCommand::new("D100".parse().unwrap(), 2));
+        codec.encode(Message::Request(Request::new(address, 0, typed)), &mut buf).unwrap();
+        let (raw, typed) = buf.split_at(buf.len() / 2);
+        assert_eq!(raw, typed);
+
+        let bad_code = Command::Raw(RawCommand::new("wr".to_string(), String::new()));
+        assert!(codec.encode(Message::Request(Request::new(address, 0, bad_code)), &mut buf).is_err());
+        let bad_body = Command::Raw(RawCommand::new("WR".to_string(), "D0\x03".to_string()));
+        assert!(codec.encode(Message::Request(Request::new(address, 0, bad_body)), &mut buf).is_err());
+
+        assert_eq!(Response::from_words(address, &[0x1234, 0xABCD]).data, "1234ABCD");
+        assert_eq!(Response::new(address, "1234ABCD").words().unwrap(), [0x1234, 0xABCD]);
+        assert_eq!(Response::from_bits(address, &[true, false]).bits().unwrap(), [true, false]);
+        assert!(matches!(Response::new(address, "123").words(), Err(DecodeError::DataLength { expected: 4, actual: 3 })));
+        assert!(matches!(Response::new(address, "12G4").words(), Err(DecodeError::BadHex { offset: 5, .. })));
+        assert!(matches!(Response::new(address, "102").bits(), Err(DecodeError::BadBit { offset: 7 })));
+    }
+
+    #[test]
+    fn response_decoded_for_command() {
+        let address = Address::new(0, 0xFF);
+        let read_words = Command::read_words("D0".parse().unwrap(), 2);
+        let read_bits = Command::ReadBits(ReadBitsCommand::new("X0".parse().unwrap(), 3));
+        let loopback = Command::LoopbackTest(LoopbackTestCommand::new("ABC".to_string()));
+
+        let decoded = Response::new(address, "1234ABCD").decode(&read_words).unwrap();
+        assert_eq!(decoded, ResponseData::Words(vec![0x1234, 0xABCD]));
+        assert_eq!(Response::new(address, "101").decode(&read_bits).unwrap(), ResponseData::Bits(vec![true, false, true]));
+        assert_eq!(Response::new(address, "F3").decode(&Command::ReadPlcType).unwrap(), ResponseData::PlcType(PlcModel::Fx3U));
+        assert_eq!(Response::new(address, "03ABC").decode(&loopback).unwrap(), ResponseData::Loopback("ABC".to_string()));
+
+        let short = Response::new(address, "1234").decode(&read_words);
+        assert!(matches!(short, Err(DecodeError::DataLength { expected: 8, actual: 4 })));
+        assert!(matches!(Response::new(address, "1234ABCX").decode(&read_words), Err(DecodeError::BadHex { offset: 9, .. })));
+        assert!(matches!(Response::new(address, "1A1").decode(&read_bits), Err(DecodeError::BadBit { offset: 6 })));
+        assert_eq!(Response::new(address, "02AB").decode(&loopback).unwrap(), ResponseData::Loopback("AB".to_string()));
+        assert!(matches!(Response::new(address, "02ABC").decode(&loopback), Err(DecodeError::DataLength { expected: 2, actual: 3 })));
+        assert!(matches!(Response::new(address, &b"03A\xC3C"[..]).decode(&loopback), Err(DecodeError::NonAscii { offset: 8 })));
+        assert!(matches!(Response::new(address, "").decode(&Command::RemoteRun), Err(DecodeError::NoResponseData(_))));
+    }
+
+    #[test]
+    fn encode_decode_bit_commands() {
+        let mut codec = FxCodec::new();
+        let mut buf = BytesMut::new();
+        let address = Address::new(5, 0xFF);
+        let read = Command::ReadBits(ReadBitsCommand::new("X40".parse().unwrap(), 256));
+        let write = Command::WriteBits(WriteBitsCommand::new("M10".parse().unwrap(), vec![true, false, true]));
+        codec.encode(Message::Request(Request::new(address, 0, read)), &mut buf).unwrap();
+        assert_eq!(&buf[..], b"\x0505FFBR0X00400031\r\n");
+        codec.encode(Message::Request(Request::new(address, 0, write)), &mut buf).unwrap();
+
+        match codec.decode(&mut buf) {
+            Ok(Some(Message::Request(Request { command: Command::ReadBits(c), .. }))) => {
+                assert_eq!(c.head_device.to_string(), "X40");
+                assert_eq!(c.number_of_device_points, 256);
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+        match codec.decode(&mut buf) {
+            Ok(Some(Message::Request(Request { command: Command::WriteBits(c), .. }))) => assert_eq!(c.data, [true, false, true]),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        let too_many = Command::ReadBits(ReadBitsCommand::new("X0".parse().unwrap(), 257));
+        assert!(codec.encode(Message::Request(Request::new(address, 0, too_many)), &mut buf).is_err());
+        let bad_data = Command::WriteBits(WriteBitsCommand { number_of_device_points: 2, ..WriteBitsCommand::new("M0".parse().unwrap(), vec![true]) });
+        assert!(codec.encode(Message::Request(Request::new(address, 0, bad_data)), &mut buf).is_err());
+
+        let word_devices = [
+            Command::ReadBits(ReadBitsCommand::new("D100".parse().unwrap(), 1)),
+            Command::WriteBits(WriteBitsCommand::new("TN10".parse().unwrap(), vec![true])),
+            Command::TestBits(TestBitsCommand::new(vec![("M0".parse().unwrap(), true), ("R0".parse().unwrap(), true)])),
+        ];
+        for command in word_devices {
+            let error = codec.encode(Message::Request(Request::new(address, 0, command)), &mut buf).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn encode_decode_test_commands() {
+        let mut codec = FxCodec::new();
+        let mut buf = BytesMut::new();
+        let address = Address::new(0, 0xFF);
+        let bits = vec![("M10".parse().unwrap(), true), ("Y7".parse().unwrap(), false)];
+        let words = vec![("D100".parse().unwrap(), 0x1234), ("CN10".parse().unwrap(), 0xABCD)];
+        codec.encode(Message::Request(Request::new(address, 0, Command::TestBits(TestBitsCommand::new(bits.clone())))), &mut buf).unwrap();
+        codec.encode(Message::Request(Request::new(address, 0, Command::TestWords(TestWordsCommand::new(words.clone())))), &mut buf).unwrap();
+        assert!(buf.starts_with(b"\x0500FFBT002M00101Y00070"));
+
+        match codec.decode(&mut buf) {
+            Ok(Some(Message::Request(Request { command: Command::TestBits(c), .. }))) => assert_eq!(c.points, bits),
+            other => panic!("unexpected result {:?}", other),
+        }
+        match codec.decode(&mut buf) {
+            Ok(Some(Message::Request(Request { command: Command::TestWords(c), .. }))) => assert_eq!(c.points, words),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        let too_many = vec![("D0".parse().unwrap(), 0); MAX_TEST_WORDS + 1];
+        let request = Request::new(address, 0, Command::TestWords(TestWordsCommand::new(too_many)));
+        assert!(codec.encode(Message::Request(request), &mut buf).is_err());
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn encode_decode_control_commands() {
+        let mut codec = FxCodec::new();
+        let mut buf = BytesMut::new();
+        let address = Address::new(0, 0xFF);
+        codec.encode(Message::Request(Request::new(address, 0, Command::RemoteRun)), &mut buf).unwrap();
+        assert_eq!(&buf[..], b"\x0500FFRR0C0\r\n");
+        codec.encode(Message::Request(Request::new(address, 0, Command::RemoteStop)), &mut buf).unwrap();
+        codec.encode(Message::Request(Request::new(address, 0, Command::ReadPlcType)), &mut buf).unwrap();
+        for code in ["RR", "RS", "PC"] {
+            match codec.decode(&mut buf) {
+                Ok(Some(Message::Request(r))) => assert_eq!(r.command.code(), code),
+                other => panic!("unexpected result {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn encode_decode_global_command() {
+        let mut codec = FxCodec::new();
+        let mut buf = BytesMut::new();
+        let address = Address::new(GLOBAL_STATION, 0xFF);
+        codec.encode(Message::Request(Request::new(address, 0, Command::Global(GlobalCommand::new(true)))), &mut buf).unwrap();
+        assert_eq!(&buf[..], b"\x05FFFFGW0117\r\n");
+        match codec.decode(&mut buf) {
+            Ok(Some(Message::Request(Request { address, command: Command::Global(c), .. }))) => {
+                assert_eq!(address.station, GLOBAL_STATION);
+                assert!(c.set);
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn encode_decode_loopback_command() {
+        let mut codec = FxCodec::new();
+        let mut buf = BytesMut::new();
+        let address = Address::new(0, 0xFF);
+        let command = Command::LoopbackTest(LoopbackTestCommand::new("ABCDE".to_string()));
+        codec.encode(Message::Request(Request::new(address, 0, command)), &mut buf).unwrap();
+        assert_eq!(&buf[..], b"\x0500FFTT005ABCDE78\r\n");
+        match codec.decode(&mut buf) {
+            Ok(Some(Message::Request(Request { command: Command::LoopbackTest(c), .. }))) => assert_eq!(c.data, "ABCDE"),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        let command = Command::LoopbackTest(LoopbackTestCommand::new("AB\nCD".to_string()));
+        assert!(codec.encode(Message::Request(Request::new(address, 0, command)), &mut buf).is_err());
+        let command = Command::LoopbackTest(LoopbackTestCommand::new("A".repeat(MAX_LOOPBACK_CHARACTERS + 1)));
+        assert!(codec.encode(Message::Request(Request::new(address, 0, command)), &mut buf).is_err());
+    }
+
+    /// xorshift, good enough to generate noise without pulling in a dependency.
+    struct Noise(u64);
+    impl Noise {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+        fn byte(&mut self) -> u8 {
+            const ALPHABET: &[u8] = b"\x02\x03\x05\x06\x15\r\n0123456789ABCDEFWRD";
+            let n = self.next();
+            if n.is_multiple_of(4) {
+                (n >> 8) as u8
+            } else {
+                ALPHABET[(n >> 8) as usize % ALPHABET.len()]
+            }
+        }
+        fn codec(&mut self) -> FxCodec {
+            let format = if self.next() & 1 == 0 { ProtocolFormat::Format1 } else { ProtocolFormat::Format4 };
+            let sum_check = [SumCheck::Enabled, SumCheck::Disabled, SumCheck::Tolerant][self.next() as usize % 3];
+            FxCodec::new().with_format(format).with_sum_check(sum_check)
+        }
+    }
+
+    #[test]
+    fn decode_random_bytes_never_panics() {
+        let mut noise = Noise(0x2545_F491_4F6C_DD1D);
+        for _ in 0..2000 {
+            let mut codec = noise.codec();
+            let mut buf = BytesMut::new();
+            let len = noise.next() % 64;
+            for _ in 0..len {
+                buf.put_u8(noise.byte());
+                // feed byte by byte, the way a slow serial line delivers them
+                while let Ok(Some(_)) | Err(_) = codec.decode(&mut buf) {}
+            }
+        }
+    }
+
+    #[test]
+    fn decode_corrupted_frames_never_panics() {
+        let mut noise = Noise(0x9E37_79B9_7F4A_7C15);
+        let frames: [&[u8]; 4] = [
+            b"\x0500FFWW0M0640022347AB9605\n",
+            b"\x0500FFWR0D01060232\n",
+            b"\x0200FF1234\x03B9\r\n",
+            b"\x1500FF02\r\n",
+        ];
+        for _ in 0..5000 {
+            let frame = frames[noise.next() as usize % frames.len()];
+            let mut corrupted = frame.to_vec();
+            for _ in 0..1 + noise.next() % 3 {
+                if corrupted.is_empty() {
+                    break;
+                }
+                let position = noise.next() as usize % corrupted.len();
+                match noise.next() % 3 {
+                    0 => corrupted[position] = noise.byte(),
+                    1 => corrupted.truncate(position),
+                    _ => { corrupted.remove(position); },
+                }
+            }
+            corrupted.push(LF);
+            let mut codec = noise.codec();
+            let mut buf = BytesMut::from(&corrupted[..]);
+            while let Ok(Some(_)) | Err(_) = codec.decode(&mut buf) {}
+        }
+    }
+
 
 }
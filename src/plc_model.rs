@@ -0,0 +1,111 @@
+use std::fmt;
+
+/// CPU family as reported by the PC type code read with the PC command.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum PlcModel {
+    /// FX and FX2C.
+    Fx2,
+    Fx0N,
+    /// FX2N and FX2NC.
+    Fx2N,
+    /// FX1N and FX1NC.
+    Fx1N,
+    Fx1S,
+    /// FX3U and FX3UC.
+    Fx3U,
+    /// FX3G and FX3GC.
+    Fx3G,
+    Unknown(u8),
+}
+
+impl PlcModel {
+    pub fn type_code(&self) -> u8 {
+        match self {
+            PlcModel::Fx2 => 0x8D,
+            PlcModel::Fx0N => 0x8E,
+            PlcModel::Fx2N => 0x9D,
+            PlcModel::Fx1N => 0x9E,
+            PlcModel::Fx1S => 0xF1,
+            PlcModel::Fx3U => 0xF3,
+            PlcModel::Fx3G => 0xF4,
+            PlcModel::Unknown(code) => *code,
+        }
+    }
+
+    /// Most words one WR or QR request of this CPU can read.
+    pub fn max_read_words(&self) -> u8 {
+        match self {
+            PlcModel::Fx0N | PlcModel::Fx1S => 13,
+            PlcModel::Fx2 => 32,
+            _ => 64,
+        }
+    }
+
+    /// Most words one WW or QW request of this CPU can write.
+    pub fn max_write_words(&self) -> u8 {
+        match self {
+            PlcModel::Fx0N | PlcModel::Fx1S => 11,
+            PlcModel::Fx2 => 32,
+            _ => 64,
+        }
+    }
+
+    /// Most words of bit devices, 16 points each, one WR or QR request of
+    /// this CPU can read.
+    pub fn max_read_bit_words(&self) -> u8 {
+        match self {
+            PlcModel::Fx0N | PlcModel::Fx1S => 13,
+            _ => 32,
+        }
+    }
+
+    /// Most words of bit devices one WW or QW request of this CPU can write.
+    pub fn max_write_bit_words(&self) -> u8 {
+        10
+    }
+}
+
+impl From<u8> for PlcModel {
+    fn from(code: u8) -> Self {
+        match code {
+            0x8D => PlcModel::Fx2,
+            0x8E => PlcModel::Fx0N,
+            0x9D => PlcModel::Fx2N,
+            0x9E => PlcModel::Fx1N,
+            0xF1 => PlcModel::Fx1S,
+            0xF3 => PlcModel::Fx3U,
+            0xF4 => PlcModel::Fx3G,
+            code => PlcModel::Unknown(code),
+        }
+    }
+}
+
+impl fmt::Display for PlcModel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlcModel::Fx2 => write!(f, "FX/FX2C"),
+            PlcModel::Fx0N => write!(f, "FX0N"),
+            PlcModel::Fx2N => write!(f, "FX2N/FX2NC"),
+            PlcModel::Fx1N => write!(f, "FX1N/FX1NC"),
+            PlcModel::Fx1S => write!(f, "FX1S"),
+            PlcModel::Fx3U => write!(f, "FX3U/FX3UC"),
+            PlcModel::Fx3G => write!(f, "FX3G/FX3GC"),
+            PlcModel::Unknown(code) => write!(f, "unknown PLC type {:02X}", code),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn type_codes() {
+        assert_eq!(PlcModel::from(0xF3), PlcModel::Fx3U);
+        assert_eq!(PlcModel::from(0x9D).to_string(), "FX2N/FX2NC");
+        assert_eq!(PlcModel::from(0x42), PlcModel::Unknown(0x42));
+        for code in 0..=u8::MAX {
+            assert_eq!(PlcModel::from(code).type_code(), code);
+        }
+    }
+}
@@ -0,0 +1,335 @@
+use crate::{invalid_input, Error};
+
+/// Order of the two registers of a 32 bit value, e.g. D100 and D101.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub enum WordOrder {
+    /// Low word in the first register, as the PLC stores 32 bit values.
+    #[default]
+    LowFirst,
+    /// High word in the first register, for data written that way by other devices.
+    HighFirst,
+}
+
+impl WordOrder {
+    /// The registers of `value` in the order they are read and written.
+    pub fn split(&self, value: u32) -> [u16; 2] {
+        let low = value as u16;
+        let high = (value >> 16) as u16;
+        match self {
+            WordOrder::LowFirst => [low, high],
+            WordOrder::HighFirst => [high, low],
+        }
+    }
+
+    /// The value of two consecutive registers.
+    pub fn join(&self, words: [u16; 2]) -> u32 {
+        let (low, high) = match self {
+            WordOrder::LowFirst => (words[0], words[1]),
+            WordOrder::HighFirst => (words[1], words[0]),
+        };
+        (high as u32) << 16 | low as u32
+    }
+}
+
+/// The register of a one word value.
+fn one_word(words: &[u16]) -> Result<u16, Error> {
+    match words {
+        [word] => Ok(*word),
+        _ => Err(word_count(1, words.len())),
+    }
+}
+
+/// The 32 bit value of a two word value.
+fn two_words(words: &[u16], order: WordOrder) -> Result<u32, Error> {
+    match words {
+        [first, second] => Ok(order.join([*first, *second])),
+        _ => Err(word_count(2, words.len())),
+    }
+}
+
+fn word_count(expected: usize, actual: usize) -> Error {
+    Error::Protocol(format!("{} registers for a value of {}", actual, expected))
+}
+
+/// A value stored in one or more consecutive word registers.
+pub trait PlcValue: Sized {
+    /// Number of registers the value occupies.
+    const WORDS: usize;
+
+    /// Fails with [`Error::Protocol`] unless `words` holds exactly
+    /// [`WORDS`](PlcValue::WORDS) registers.
+    fn from_words(words: &[u16], order: WordOrder) -> Result<Self, Error>;
+
+    fn to_words(&self, order: WordOrder) -> Result<Vec<u16>, Error>;
+}
+
+impl PlcValue for u16 {
+    const WORDS: usize = 1;
+
+    fn from_words(words: &[u16], _order: WordOrder) -> Result<Self, Error> {
+        one_word(words)
+    }
+
+    fn to_words(&self, _order: WordOrder) -> Result<Vec<u16>, Error> {
+        Ok(vec![*self])
+    }
+}
+
+impl PlcValue for i16 {
+    const WORDS: usize = 1;
+
+    fn from_words(words: &[u16], _order: WordOrder) -> Result<Self, Error> {
+        Ok(one_word(words)? as i16)
+    }
+
+    fn to_words(&self, _order: WordOrder) -> Result<Vec<u16>, Error> {
+        Ok(vec![*self as u16])
+    }
+}
+
+impl PlcValue for u32 {
+    const WORDS: usize = 2;
+
+    fn from_words(words: &[u16], order: WordOrder) -> Result<Self, Error> {
+        two_words(words, order)
+    }
+
+    fn to_words(&self, order: WordOrder) -> Result<Vec<u16>, Error> {
+        Ok(order.split(*self).to_vec())
+    }
+}
+
+impl PlcValue for i32 {
+    const WORDS: usize = 2;
+
+    fn from_words(words: &[u16], order: WordOrder) -> Result<Self, Error> {
+        Ok(two_words(words, order)? as i32)
+    }
+
+    fn to_words(&self, order: WordOrder) -> Result<Vec<u16>, Error> {
+        Ok(order.split(*self as u32).to_vec())
+    }
+}
+
+/// IEEE 754 single precision, the floating point format of FX3 CPUs.
+impl PlcValue for f32 {
+    const WORDS: usize = 2;
+
+    fn from_words(words: &[u16], order: WordOrder) -> Result<Self, Error> {
+        Ok(f32::from_bits(two_words(words, order)?))
+    }
+
+    fn to_words(&self, order: WordOrder) -> Result<Vec<u16>, Error> {
+        Ok(order.split(self.to_bits()).to_vec())
+    }
+}
+
+/// A register holding 0 or 1, any other value reads as `true`. Bit devices
+/// are read with [`Client::read_bits`](crate::Client::read_bits).
+impl PlcValue for bool {
+    const WORDS: usize = 1;
+
+    fn from_words(words: &[u16], _order: WordOrder) -> Result<Self, Error> {
+        Ok(one_word(words)? != 0)
+    }
+
+    fn to_words(&self, _order: WordOrder) -> Result<Vec<u16>, Error> {
+        Ok(vec![*self as u16])
+    }
+}
+
+/// A BCD coded register as used by BCD/BIN instructions and thumbwheel
+/// switches, 0 to 9999.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub struct Bcd16(pub u16);
+
+/// Two BCD coded registers, 0 to 99999999.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub struct Bcd32(pub u32);
+
+fn from_bcd(bcd: u32, digits: u32) -> Result<u32, Error> {
+    (0..digits).rev().try_fold(0, |value, digit| {
+        let nibble = (bcd >> (digit * 4)) & 0xF;
+        if nibble > 9 {
+            return Err(Error::Protocol(format!("{:X} is not BCD", bcd)));
+        }
+        Ok(value * 10 + nibble)
+    })
+}
+
+fn to_bcd(value: u32, digits: u32) -> Result<u32, Error> {
+    if value >= 10u32.pow(digits) {
+        return Err(invalid_input(format!("{} doesn't fit into {} BCD digits", value, digits)).into());
+    }
+    Ok((0..digits).fold(0, |bcd, digit| bcd | (value / 10u32.pow(digit) % 10) << (digit * 4)))
+}
+
+impl PlcValue for Bcd16 {
+    const WORDS: usize = 1;
+
+    fn from_words(words: &[u16], _order: WordOrder) -> Result<Self, Error> {
+        Ok(Bcd16(from_bcd(one_word(words)? as u32, 4)? as u16))
+    }
+
+    fn to_words(&self, _order: WordOrder) -> Result<Vec<u16>, Error> {
+        Ok(vec![to_bcd(self.0 as u32, 4)? as u16])
+    }
+}
+
+impl PlcValue for Bcd32 {
+    const WORDS: usize = 2;
+
+    fn from_words(words: &[u16], order: WordOrder) -> Result<Self, Error> {
+        Ok(Bcd32(from_bcd(two_words(words, order)?, 8)?))
+    }
+
+    fn to_words(&self, order: WordOrder) -> Result<Vec<u16>, Error> {
+        Ok(order.split(to_bcd(self.0, 8)?).to_vec())
+    }
+}
+
+/// A register holding a value scaled by 10^`DECIMALS`, e.g. 235 for 23.5 °C
+/// with one decimal.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub struct Fixed16<const DECIMALS: u32>(pub i16);
+
+/// Two registers holding a value scaled by 10^`DECIMALS`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub struct Fixed32<const DECIMALS: u32>(pub i32);
+
+impl<const DECIMALS: u32> Fixed16<DECIMALS> {
+    /// The nearest representable value, `None` if `value` is out of range.
+    pub fn from_f64(value: f64) -> Option<Self> {
+        let raw = (value * 10f64.powi(DECIMALS as i32)).round();
+        (raw >= i16::MIN as f64 && raw <= i16::MAX as f64).then_some(Fixed16(raw as i16))
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        self.0 as f64 / 10f64.powi(DECIMALS as i32)
+    }
+}
+
+impl<const DECIMALS: u32> Fixed32<DECIMALS> {
+    /// The nearest representable value, `None` if `value` is out of range.
+    pub fn from_f64(value: f64) -> Option<Self> {
+        let raw = (value * 10f64.powi(DECIMALS as i32)).round();
+        (raw >= i32::MIN as f64 && raw <= i32::MAX as f64).then_some(Fixed32(raw as i32))
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        self.0 as f64 / 10f64.powi(DECIMALS as i32)
+    }
+}
+
+impl<const DECIMALS: u32> PlcValue for Fixed16<DECIMALS> {
+    const WORDS: usize = 1;
+
+    fn from_words(words: &[u16], order: WordOrder) -> Result<Self, Error> {
+        i16::from_words(words, order).map(Fixed16)
+    }
+
+    fn to_words(&self, order: WordOrder) -> Result<Vec<u16>, Error> {
+        self.0.to_words(order)
+    }
+}
+
+impl<const DECIMALS: u32> PlcValue for Fixed32<DECIMALS> {
+    const WORDS: usize = 2;
+
+    fn from_words(words: &[u16], order: WordOrder) -> Result<Self, Error> {
+        i32::from_words(words, order).map(Fixed32)
+    }
+
+    fn to_words(&self, order: WordOrder) -> Result<Vec<u16>, Error> {
+        self.0.to_words(order)
+    }
+}
+
+/// Packs ASCII text two characters per register, the first in the low byte
+/// as the PLC's ASCII instructions store it, followed by a NUL and padded to
+/// whole registers.
+pub(crate) fn pack_string(text: &str) -> Result<Vec<u16>, Error> {
+    if !text.is_ascii() {
+        return Err(invalid_input(format!("{:?} is not ASCII", text)).into());
+    }
+    let mut bytes = text.as_bytes().to_vec();
+    bytes.push(0);
+    if bytes.len() % 2 == 1 {
+        bytes.push(0);
+    }
+    Ok(bytes.chunks(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]])).collect())
+}
+
+/// Text packed by [`pack_string`], up to the first NUL or `max_len` characters.
+pub(crate) fn unpack_string(words: &[u16], max_len: usize) -> Result<String, Error> {
+    let bytes: Vec<u8> = words
+        .iter()
+        .flat_map(|word| word.to_le_bytes())
+        .take(max_len)
+        .take_while(|b| *b != 0)
+        .collect();
+    String::from_utf8(bytes)
+        .ok()
+        .filter(|text| text.is_ascii())
+        .ok_or_else(|| Error::Protocol("string registers hold non ASCII characters".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn word_order() {
+        // D100 = 0x5678 and D101 = 0x1234 hold 0x12345678
+        assert_eq!(WordOrder::LowFirst.join([0x5678, 0x1234]), 0x1234_5678);
+        assert_eq!(WordOrder::LowFirst.split(0x1234_5678), [0x5678, 0x1234]);
+        assert_eq!(WordOrder::HighFirst.join([0x5678, 0x1234]), 0x5678_1234);
+        assert_eq!(WordOrder::HighFirst.split(0x1234_5678), [0x1234, 0x5678]);
+        // DMOV K100000 D0 leaves D0 = H86A0, D1 = H0001
+        assert_eq!(WordOrder::LowFirst.join([0x86A0, 0x0001]) as i32, 100000);
+        assert_eq!(WordOrder::LowFirst.split(-2i32 as u32), [0xFFFE, 0xFFFF]);
+    }
+
+    #[test]
+    fn typed_values() {
+        let order = WordOrder::LowFirst;
+        assert_eq!(u16::from_words(&[0xFFFE], order).unwrap(), 0xFFFE);
+        assert_eq!(i16::from_words(&[0xFFFE], order).unwrap(), -2);
+        assert_eq!(u32::from_words(&[0x5678, 0x1234], order).unwrap(), 0x1234_5678);
+        // DEMOV E1.5 D0 leaves D0 = H0000, D1 = H3FC0
+        assert_eq!(f32::from_words(&[0x0000, 0x3FC0], order).unwrap(), 1.5);
+        assert_eq!((-1.5f32).to_words(order).unwrap(), [0x0000, 0xBFC0]);
+        assert!(bool::from_words(&[2], order).unwrap());
+        assert_eq!(true.to_words(order).unwrap(), [1]);
+
+        assert_eq!(Bcd16::from_words(&[0x1234], order).unwrap(), Bcd16(1234));
+        assert_eq!(Bcd16(9876).to_words(order).unwrap(), [0x9876]);
+        assert!(matches!(Bcd16::from_words(&[0x12A4], order), Err(Error::Protocol(_))));
+        assert!(Bcd16(10000).to_words(order).is_err());
+        assert_eq!(Bcd32(12345678).to_words(order).unwrap(), [0x5678, 0x1234]);
+        assert_eq!(Bcd32::from_words(&[0x5678, 0x1234], WordOrder::HighFirst).unwrap(), Bcd32(56781234));
+
+        assert_eq!(Fixed16::<1>::from_words(&[235], order).unwrap().to_f64(), 23.5);
+        assert_eq!(Fixed16::<2>::from_f64(-1.235), Some(Fixed16(-124)));
+        assert_eq!(Fixed16::<1>::from_f64(4000.0), None);
+        assert_eq!(Fixed32::<3>::from_f64(100.0).unwrap().to_words(order).unwrap(), [0x86A0, 0x0001]);
+
+        assert!(matches!(u32::from_words(&[1], order), Err(Error::Protocol(_))));
+        assert!(matches!(i16::from_words(&[], order), Err(Error::Protocol(_))));
+        assert!(matches!(Bcd16::from_words(&[1, 2], order), Err(Error::Protocol(_))));
+    }
+
+    #[test]
+    fn strings() {
+        // "AB" in D0 is H4241
+        assert_eq!(pack_string("AB").unwrap(), [0x4241, 0x0000]);
+        assert_eq!(pack_string("ABC").unwrap(), [0x4241, 0x0043]);
+        assert_eq!(pack_string("").unwrap(), [0x0000]);
+        assert!(pack_string("Grüße").is_err());
+
+        assert_eq!(unpack_string(&[0x4241, 0x0043, 0x4545], 10).unwrap(), "ABC");
+        assert_eq!(unpack_string(&[0x4241, 0x4443], 3).unwrap(), "ABC");
+        assert_eq!(unpack_string(&[0x4241, 0x4443], 10).unwrap(), "ABCD");
+        assert!(matches!(unpack_string(&[0x41C3], 2), Err(Error::Protocol(_))));
+    }
+}